@@ -0,0 +1,60 @@
+// 读取吞吐随线程数的变化
+// cargo bench --bench concurrency
+extern crate koundb;
+extern crate tempfile;
+
+use koundb::{Db, Options};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+const KEYS: usize = 10_000;
+const READS_PER_THREAD: usize = 200_000;
+
+// 线程数从1到8,每个线程读取READS_PER_THREAD次,返回每秒读取次数
+fn bench_reads(name: &str, options: Options) {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    for i in 0..KEYS {
+        db.put(format!("key{:06}", i), vec![i as u8; 100]).unwrap();
+    }
+    let db = Arc::new(db);
+    for &threads in &[1, 2, 4, 8] {
+        let start = Instant::now();
+        let handles: Vec<_> = (0..threads)
+            .map(|t| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    let mut index = t * 7919;
+                    for _ in 0..READS_PER_THREAD {
+                        index = (index + 104_729) % KEYS;
+                        let value = db.get(format!("key{:06}", index)).unwrap();
+                        assert_eq!(value.map(|value| value.len()), Some(100));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        let reads = (threads * READS_PER_THREAD) as f64;
+        println!(
+            "{:<10} threads: {}  reads/s: {:>12.0}",
+            name,
+            threads,
+            reads / elapsed
+        );
+    }
+}
+
+fn main() {
+    bench_reads("cached", Options::default());
+    bench_reads(
+        "uncached",
+        Options {
+            cache_capacity: 0,
+            ..Options::default()
+        },
+    );
+}
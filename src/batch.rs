@@ -0,0 +1,54 @@
+use std::collections::btree_map::{self, BTreeMap};
+
+// 一批原子写入,Db::write时全部生效或全部不生效
+// 同一key的多次操作只保留最后一次
+#[derive(Debug, Default, Clone)]
+pub struct WriteBatch {
+    // key-value, value为None表示删除
+    ops: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        WriteBatch::default()
+    }
+
+    // 写入key-value
+    pub fn put<K, V>(&mut self, key: K, value: V)
+    where
+        Vec<u8>: From<K>,
+        Vec<u8>: From<V>,
+    {
+        self.ops.insert(Vec::from(key), Some(Vec::from(value)));
+    }
+
+    // 删除key
+    pub fn delete<K>(&mut self, key: K)
+    where
+        Vec<u8>: From<K>,
+    {
+        self.ops.insert(Vec::from(key), None);
+    }
+
+    // 批次中不同key的个数
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear()
+    }
+}
+
+impl IntoIterator for WriteBatch {
+    type Item = (Vec<u8>, Option<Vec<u8>>);
+    type IntoIter = btree_map::IntoIter<Vec<u8>, Option<Vec<u8>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ops.into_iter()
+    }
+}
@@ -1,123 +1,478 @@
-use indexmap::IndexMap;
-use indexmap::map::{Iter,IterMut};
-use std::iter::{IntoIterator,FromIterator};
+use eviction::{CachePolicy, Policy};
+use std::borrow::Borrow;
+use std::collections::hash_map;
+use std::collections::HashMap;
+use std::iter::{FromIterator, IntoIterator};
+use std::sync::Mutex;
+use util::hash_value;
 
-use std::hash::Hash;
 use std::fmt;
-use std::fmt::{Debug,Formatter};
+use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
 
+// 计算键与值的权重,缓存中所有键的权重之和不超过容量
+pub type Weigher<K, V> = fn(&K, &V) -> usize;
+
+// 每个键的权重都为1,容量即键的数量
+fn unit_weight<K, V>(_: &K, _: &V) -> usize {
+    1
+}
 
-// LRU缓存
-// innercache : 保持插入顺序的的hashmap
-// capacity : hashmap的容量
+// 带权重的缓存
+// map : 键到(值,权重)的hashmap
+// policy : 淘汰策略,决定缓存满时移除哪个键
+// weigher : 计算权重的函数
+// weight : 当前所有键的权重之和
+// capacity : 权重之和的上限,权重超过capacity的键不会被缓存
 pub struct Cache<K, V> {
-    innercache: IndexMap<K, V>,
+    map: HashMap<K, (V, usize)>,
+    policy: Box<dyn Policy<K>>,
+    weigher: Weigher<K, V>,
+    weight: usize,
     capacity: usize,
 }
 
-impl<K,V> Debug for Cache<K,V> 
+impl<K, V> Debug for Cache<K, V>
 where
     K: Hash + Eq + Clone + Debug,
-    V: Debug {
-    // add code here
-    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error>{
-        let kvs:Vec<(&K,&V)> = self.innercache.iter().collect();
-        write!(f, "{:?}",kvs)
+    V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        let kvs: Vec<(&K, &V)> = self.iter().collect();
+        write!(f, "{:?}", kvs)
     }
 }
 
-
-impl<K, V> Cache<K, V> {
-    // 设定缓存最大容量
+impl<K, V> Cache<K, V>
+where
+    K: Hash + Eq + Clone + Send + 'static,
+{
+    // 设定缓存最大容量,容量为键的数量,使用LRU淘汰
     pub fn with_capacity(n: usize) -> Self {
-        if n == 0 {
+        Cache::with_policy(n, CachePolicy::Lru, unit_weight)
+    }
+    // 使用policy淘汰,weigher计算每个键的权重
+    pub fn with_policy(capacity: usize, policy: CachePolicy, weigher: Weigher<K, V>) -> Self {
+        if capacity == 0 {
             panic!("cann't allocate if n is zero");
         }
         Cache {
-            innercache: IndexMap::with_capacity(n),
-            capacity: n,
+            map: HashMap::new(),
+            policy: policy.build(capacity),
+            weigher,
+            weight: 0,
+            capacity,
+        }
+    }
+}
+
+impl<K, V> Cache<K, V> {
+    // 缓存中键的数量
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+    // 当前所有键的权重之和
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+    // 返回一个不可变迭代器,顺序不定
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            inner: self.map.iter(),
+        }
+    }
+    // 返回一个可变迭代器,顺序不定
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut {
+            inner: self.map.iter_mut(),
         }
     }
 }
+
 impl<K, V> Cache<K, V>
 where
     K: Hash + Eq + Clone,
 {
-    // FIXME: 只读
-    pub fn lookup(&self,key:&K) -> Option<&V> {
-        unimplemented!()
+    // 只读地查看键,不算作一次访问
+    pub fn lookup<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.get(key).map(|(val, _)| val)
     }
 
-    // 得到缓存中键的引用
-    pub fn get(&mut self, key: &K) -> Option<&V> {
-        match self.innercache.swap_remove_full(key) {
-            Some((_, k, v)) => {
-                self.innercache.insert(k, v);
-            }
-            None => {}
-        }
-        self.innercache.get(key)
+    // 得到缓存中键的引用,并通知淘汰策略键被访问
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let (key, (val, _)) = self.map.get_key_value(key)?;
+        self.policy.access(key);
+        Some(val)
     }
-    // 更新键,在键满时,移除index=0的键
+    // 更新键,权重之和超过容量时按淘汰策略移除键,可能移除刚加入的键
     pub fn set(&mut self, key: K, val: V) {
-        self.innercache.remove(&key);
-        if (self.innercache.len()==self.capacity){
-            self.innercache.swap_remove_index(0);
+        self.remove(&key);
+        let weight = (self.weigher)(&key, &val);
+        if weight > self.capacity {
+            return;
+        }
+        self.map.insert(key.clone(), (val, weight));
+        self.policy.insert(key, weight);
+        self.weight += weight;
+        while self.weight > self.capacity {
+            match self.policy.evict() {
+                Some(victim) => {
+                    if let Some((_, weight)) = self.map.remove(&victim) {
+                        self.weight -= weight;
+                    }
+                }
+                None => break,
+            }
         }
-        self.innercache.insert(key, val);
     }
-    // 返回一个不可变迭代器
-    pub fn iter(&self) -> Iter<K,V> {
-        self.innercache.iter()
+    // 移除键,返回键对应的值
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let (key, (val, weight)) = self.map.remove_entry(key)?;
+        self.policy.remove(&key);
+        self.weight -= weight;
+        Some(val)
     }
-    // 返回一个可变迭代器
-    pub fn iter_mut(&mut self) -> IterMut<K,V> {
-        self.innercache.iter_mut()
+}
+
+// (&self.key, &self.value)
+pub struct Iter<'a, K, V> {
+    inner: hash_map::Iter<'a, K, (V, usize)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(key, (val, _))| (key, val))
     }
 }
+
+// (&self.key, &mut self.value)
+pub struct IterMut<'a, K, V> {
+    inner: hash_map::IterMut<'a, K, (V, usize)>,
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(key, (val, _))| (key, val))
+    }
+}
+
 // 不可变迭代器
-// (&self.key, &self.value)
-impl<'a,K,V> IntoIterator for &'a Cache<K,V> 
-where K: Hash + Eq + Clone {
-    type Item = (&'a K,&'a V);
-    type IntoIter = Iter<'a,K,V>;
+impl<'a, K, V> IntoIterator for &'a Cache<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
     fn into_iter(self) -> Self::IntoIter {
         self.iter()
     }
 }
 // 可变迭代器
-// (&self.key, &mut self.value) 
-impl<'a,K,V> IntoIterator for &'a mut  Cache<K,V> 
-where K: Hash + Eq + Clone {
-    type Item = (&'a K,&'a mut V);
-    type IntoIter = IterMut<'a,K,V>;
+impl<'a, K, V> IntoIterator for &'a mut Cache<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
     fn into_iter(self) -> Self::IntoIter {
         self.iter_mut()
     }
 }
-// collect
-impl<K,V> FromIterator<(K,V)> for Cache<K,V> 
-where K: Hash + Eq + Clone{
-    fn from_iter<I:IntoIterator<Item = (K,V)>>(iterable:I) -> Self {
-        let map = IndexMap::from_iter(iterable);
-        let capacity = map.capacity();
-        Cache{
-            innercache:map,
-            capacity:capacity
-        }
-    }
-}
-// #[cfg(test)]
-// mod tests {
-//     #[test]
-//     fn it_works() {
-//         use indexmap::IndexMap;
-//         let mut letters = IndexMap::new();
-//         for ch in "a short treatise on fungi".chars() {
-//             *letters.entry(ch).or_insert(0) += 1;
-//         }
-
-//         assert_eq!(letters[&'s'], 2);
-//         assert_eq!(2 + 2, 4);
-//     }
-// }
+// collect,容量为元素个数,后出现的元素较新
+impl<K, V> FromIterator<(K, V)> for Cache<K, V>
+where
+    K: Hash + Eq + Clone + Send + 'static,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iterable: I) -> Self {
+        let kvs: Vec<(K, V)> = iterable.into_iter().collect();
+        let mut cache = Cache::with_capacity(kvs.len().max(1));
+        for (key, val) in kvs {
+            cache.set(key, val);
+        }
+        cache
+    }
+}
+
+// 分片的最大数量
+const MAX_SHARDS: usize = 16;
+// 每个分片的最小容量,容量较小时分片较少
+const MIN_SHARD_CAPACITY: usize = 1 << 20;
+
+// 线程安全的缓存,键按哈希值分到各个分片,每个分片各自加锁
+// 每个分片的容量为总容量的1/n,淘汰在分片内进行
+// 分片的容量不小于需要缓存的最大项,总容量不足时只有一个分片
+pub struct ShardedCache<K, V> {
+    shards: Vec<Mutex<Cache<K, V>>>,
+}
+
+impl<K, V> Debug for ShardedCache<K, V> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("ShardedCache")
+            .field("shards", &self.shards.len())
+            .finish()
+    }
+}
+
+impl<K, V> ShardedCache<K, V>
+where
+    K: Hash + Eq + Clone + Send + 'static,
+{
+    // maxentry为需要缓存的最大项的权重,分片数量随之减少
+    pub fn new(
+        capacity: usize,
+        maxentry: usize,
+        policy: CachePolicy,
+        weigher: Weigher<K, V>,
+    ) -> Self {
+        let count = (capacity / maxentry.max(MIN_SHARD_CAPACITY)).clamp(1, MAX_SHARDS);
+        let shardcapacity = capacity.div_ceil(count);
+        ShardedCache {
+            shards: (0..count)
+                .map(|_| Mutex::new(Cache::with_policy(shardcapacity, policy, weigher)))
+                .collect(),
+        }
+    }
+    // 键所在的分片
+    fn shard<Q>(&self, key: &Q) -> &Mutex<Cache<K, V>>
+    where
+        Q: ?Sized + Hash,
+    {
+        &self.shards[hash_value(key) as usize % self.shards.len()]
+    }
+    // 得到键对应值的副本
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: Clone,
+    {
+        self.shard(key).lock().unwrap().get(key).cloned()
+    }
+    pub fn set(&self, key: K, val: V) {
+        self.shard(&key).lock().unwrap().set(key, val)
+    }
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.shard(key).lock().unwrap().remove(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, ShardedCache, Weigher};
+    use eviction::CachePolicy;
+    use std::sync::Arc;
+    use std::thread;
+
+    // 用线性查找实现的带权重LRU,作为对照
+    struct Model {
+        entries: Vec<(u32, u32)>,
+        capacity: usize,
+        weigher: Weigher<u32, u32>,
+    }
+
+    impl Model {
+        fn get(&mut self, key: u32) -> Option<u32> {
+            let pos = self.entries.iter().position(|&(k, _)| k == key)?;
+            let entry = self.entries.remove(pos);
+            self.entries.push(entry);
+            Some(entry.1)
+        }
+        fn set(&mut self, key: u32, val: u32) {
+            self.remove(key);
+            if (self.weigher)(&key, &val) > self.capacity {
+                return;
+            }
+            self.entries.push((key, val));
+            while self.weight() > self.capacity {
+                self.entries.remove(0);
+            }
+        }
+        fn weight(&self) -> usize {
+            self.entries.iter().map(|(k, v)| (self.weigher)(k, v)).sum()
+        }
+        fn remove(&mut self, key: u32) -> Option<u32> {
+            let pos = self.entries.iter().position(|&(k, _)| k == key)?;
+            Some(self.entries.remove(pos).1)
+        }
+    }
+
+    // 线性同余伪随机数
+    fn next_rand(state: &mut u64) -> u32 {
+        *state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (*state >> 33) as u32
+    }
+
+    fn unit(_: &u32, _: &u32) -> usize {
+        1
+    }
+
+    // 权重为1到8
+    fn varied(key: &u32, val: &u32) -> usize {
+        ((key ^ val) % 8 + 1) as usize
+    }
+
+    // 按键排序的缓存内容
+    fn entries(cache: &Cache<u32, u32>) -> Vec<(u32, u32)> {
+        let mut kvs: Vec<(u32, u32)> = cache.iter().map(|(&k, &v)| (k, v)).collect();
+        kvs.sort();
+        kvs
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = Cache::with_capacity(3);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        assert_eq!(cache.get(&1), Some(&1));
+        cache.set(4, 4);
+        assert_eq!(cache.lookup(&2), None);
+        // lookup不改变顺序
+        assert_eq!(cache.lookup(&3), Some(&3));
+        cache.set(5, 5);
+        assert_eq!(cache.lookup(&3), None);
+        // 更新已有的键使其成为最近访问的键
+        cache.set(1, 10);
+        cache.set(6, 6);
+        assert_eq!(entries(&cache), vec![(1, 10), (5, 5), (6, 6)]);
+    }
+
+    #[test]
+    fn remove_and_iter_mut() {
+        let mut cache: Cache<u32, u32> = (0..5).map(|i| (i, i)).collect();
+        assert_eq!(cache.remove(&0), Some(0));
+        assert_eq!(cache.remove(&3), Some(3));
+        assert_eq!(cache.remove(&3), None);
+        assert_eq!(entries(&cache), vec![(1, 1), (2, 2), (4, 4)]);
+        for (_, val) in cache.iter_mut() {
+            *val *= 10;
+        }
+        // 容量为collect时的元素个数
+        cache.set(5, 50);
+        cache.set(6, 60);
+        cache.set(7, 70);
+        assert_eq!(
+            entries(&cache),
+            vec![(2, 20), (4, 40), (5, 50), (6, 60), (7, 70)]
+        );
+        assert_eq!(cache.len(), 5);
+    }
+
+    #[test]
+    fn matches_model_over_long_sequences() {
+        let weighers: [Weigher<u32, u32>; 2] = [unit, varied];
+        for &weigher in weighers.iter() {
+            for &capacity in &[1, 2, 7, 64] {
+                let mut cache = Cache::with_policy(capacity, CachePolicy::Lru, weigher);
+                let mut model = Model {
+                    entries: Vec::new(),
+                    capacity,
+                    weigher,
+                };
+                let mut state = capacity as u64;
+                for step in 0..20_000 {
+                    let key = next_rand(&mut state) % (capacity as u32 * 2 + 1);
+                    match next_rand(&mut state) % 10 {
+                        0..=3 => assert_eq!(cache.get(&key).cloned(), model.get(key)),
+                        4..=8 => {
+                            cache.set(key, step);
+                            model.set(key, step);
+                        }
+                        _ => assert_eq!(cache.remove(&key), model.remove(key)),
+                    }
+                    let mut expected = model.entries.clone();
+                    expected.sort();
+                    assert_eq!(entries(&cache), expected);
+                    assert_eq!(cache.weight(), model.weight());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn weight_bounds_capacity() {
+        let mut cache =
+            Cache::with_policy(10, CachePolicy::Lru, |_: &u32, val: &Vec<u8>| val.len());
+        cache.set(1, vec![0; 4]);
+        cache.set(2, vec![0; 4]);
+        cache.set(3, vec![0; 4]);
+        assert_eq!(cache.lookup(&1), None);
+        assert_eq!(cache.weight(), 8);
+        // 超过容量的值不会被缓存,也不挤掉其他键
+        cache.set(4, vec![0; 11]);
+        assert_eq!(cache.lookup(&4), None);
+        assert_eq!(cache.len(), 2);
+        // 一个较大的值可以挤掉多个键
+        cache.set(5, vec![0; 10]);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.weight(), 10);
+    }
+
+    #[test]
+    fn sharded_cache_is_shared_between_threads() {
+        let cache = Arc::new(ShardedCache::new(1 << 24, 1, CachePolicy::Lru, unit));
+        let handles: Vec<_> = (0..4u32)
+            .map(|t| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        let key = t * 1000 + i;
+                        cache.set(key, key);
+                        assert_eq!(cache.get(&key), Some(key));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!((0..4000u32).all(|key| cache.get(&key) == Some(key)));
+        assert_eq!(cache.remove(&1234), Some(1234));
+        assert_eq!(cache.get(&1234), None);
+
+        // 容量较小时只有一个分片,容量不会被分片摊薄
+        let small = ShardedCache::new(4, 1, CachePolicy::Clock, unit);
+        for key in 0..4u32 {
+            small.set(key, key);
+        }
+        assert!((0..4u32).all(|key| small.get(&key) == Some(key)));
+    }
+
+    #[test]
+    fn sharded_cache_holds_entries_up_to_maxentry() {
+        let len = |_: &u32, val: &Vec<u8>| val.len();
+        // 8MiB的容量按1MiB分片时放不下2MiB的值
+        let cache = ShardedCache::new(8 << 20, 1, CachePolicy::Lru, len);
+        cache.set(1, vec![0; 2 << 20]);
+        assert_eq!(cache.get(&1), None);
+
+        let cache = ShardedCache::new(8 << 20, 4 << 20, CachePolicy::Lru, len);
+        cache.set(1, vec![0; 2 << 20]);
+        cache.set(2, vec![0; 4 << 20]);
+        assert_eq!(cache.get(&1).map(|val| val.len()), Some(2 << 20));
+        assert_eq!(cache.get(&2).map(|val| val.len()), Some(4 << 20));
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt;
+use std::time::Duration;
+
+// 一个已封存.data文件的统计信息,作为压缩策略的输入
+// fileid为文件创建时的毫秒时间戳
+// used为已使用的空间,dead为其中等待压缩的空间,均为字节数
+// smallest_key与largest_key为文件中存活key的范围,没有存活key时为None
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStats {
+    pub fileid: u64,
+    pub used: u32,
+    pub dead: u32,
+    pub live_keys: usize,
+    pub smallest_key: Option<Vec<u8>>,
+    pub largest_key: Option<Vec<u8>>,
+}
+
+impl FileStats {
+    // 等待压缩的空间占已用空间的比例
+    pub fn dead_ratio(&self) -> f32 {
+        if self.used == 0 {
+            0.0
+        } else {
+            (f64::from(self.dead) / f64::from(self.used)) as f32
+        }
+    }
+    // 存活记录占用的空间
+    pub fn live(&self) -> u32 {
+        self.used - self.dead
+    }
+}
+
+// 压缩策略,从已封存文件中选出应当压缩的文件
+// now为当前的毫秒时间戳,被快照引用的文件在选出后仍会被跳过
+pub trait CompactionPolicy: fmt::Debug + Send + Sync {
+    fn select(&self, files: &[FileStats], now: u64) -> Vec<u64>;
+}
+
+// 压缩等待压缩空间比例不低于ratio的文件
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeadRatio {
+    pub ratio: f32,
+}
+
+impl Default for DeadRatio {
+    fn default() -> DeadRatio {
+        DeadRatio { ratio: 0.25 }
+    }
+}
+
+impl CompactionPolicy for DeadRatio {
+    fn select(&self, files: &[FileStats], _now: u64) -> Vec<u64> {
+        files
+            .iter()
+            .filter(|file| file.dead > 0 && file.dead_ratio() >= self.ratio)
+            .map(|file| file.fileid)
+            .collect()
+    }
+}
+
+// 存活数据小于max_live的文件达到min_files个时一起压缩,合并为更少的文件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeTiered {
+    pub max_live: u32,
+    pub min_files: usize,
+}
+
+impl Default for SizeTiered {
+    fn default() -> SizeTiered {
+        SizeTiered {
+            max_live: 4 << 20,
+            min_files: 4,
+        }
+    }
+}
+
+impl CompactionPolicy for SizeTiered {
+    fn select(&self, files: &[FileStats], _now: u64) -> Vec<u64> {
+        let small: Vec<u64> = files
+            .iter()
+            .filter(|file| file.live() < self.max_live)
+            .map(|file| file.fileid)
+            .collect();
+        if small.len() >= self.min_files.max(1) {
+            small
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+// 压缩创建时间超过max_age且有等待压缩空间的文件,使过期记录和碎片最终被回收
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AgeBased {
+    pub max_age: Duration,
+}
+
+impl CompactionPolicy for AgeBased {
+    fn select(&self, files: &[FileStats], now: u64) -> Vec<u64> {
+        let max_age = self.max_age.as_millis() as u64;
+        files
+            .iter()
+            .filter(|file| file.dead > 0 && now.saturating_sub(file.fileid) >= max_age)
+            .map(|file| file.fileid)
+            .collect()
+    }
+}
+
+// 压缩存活key与[start, end)相交的文件,end为None时不设上界
+// 适用于删除一段key之后尽快回收空间
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRange {
+    pub start: Vec<u8>,
+    pub end: Option<Vec<u8>>,
+}
+
+impl CompactionPolicy for KeyRange {
+    fn select(&self, files: &[FileStats], _now: u64) -> Vec<u64> {
+        files
+            .iter()
+            .filter(|file| match (&file.smallest_key, &file.largest_key) {
+                (Some(smallest), Some(largest)) => {
+                    *largest >= self.start && self.end.as_ref().is_none_or(|end| smallest < end)
+                }
+                // 没有存活key的文件可以直接删除
+                _ => file.used > 0,
+            })
+            .map(|file| file.fileid)
+            .collect()
+    }
+}
+
+// 压缩过滤器对一条存活记录的处理
+// Keep: 原样复制到新位置
+// Remove: 删除key,原位置写入删除记录后随原文件一起删除
+// ChangeValue: 用新value替换,新记录与写入一样压缩、加密和分配
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterDecision {
+    Keep,
+    Remove,
+    ChangeValue(Vec<u8>),
+}
+
+// 压缩过滤器,压缩时对每条存活且未过期的记录调用,value为解压后的value
+// 只有被压缩的文件中的记录会经过过滤器
+pub trait CompactionFilter: fmt::Debug + Send + Sync {
+    fn filter(&self, key: &[u8], value: &[u8]) -> FilterDecision;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(fileid: u64, used: u32, dead: u32, keys: Option<(&str, &str)>) -> FileStats {
+        FileStats {
+            fileid,
+            used,
+            dead,
+            live_keys: keys.map_or(0, |_| 1),
+            smallest_key: keys.map(|(smallest, _)| smallest.as_bytes().to_vec()),
+            largest_key: keys.map(|(_, largest)| largest.as_bytes().to_vec()),
+        }
+    }
+
+    #[test]
+    fn dead_ratio_selects_fragmented_files() {
+        let files = [
+            file(1, 100, 10, Some(("a", "b"))),
+            file(2, 100, 50, Some(("a", "b"))),
+            file(3, 0, 0, None),
+        ];
+        assert_eq!(DeadRatio::default().select(&files, 0), vec![2]);
+        assert_eq!(DeadRatio { ratio: 0.1 }.select(&files, 0), vec![1, 2]);
+    }
+
+    #[test]
+    fn size_tiered_waits_for_enough_small_files() {
+        let policy = SizeTiered {
+            max_live: 100,
+            min_files: 3,
+        };
+        let mut files = vec![
+            file(1, 50, 0, Some(("a", "b"))),
+            file(2, 500, 0, Some(("a", "b"))),
+            file(3, 120, 40, Some(("a", "b"))),
+        ];
+        assert!(policy.select(&files, 0).is_empty());
+        files.push(file(4, 10, 0, Some(("a", "b"))));
+        assert_eq!(policy.select(&files, 0), vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn age_based_selects_old_fragmented_files() {
+        let policy = AgeBased {
+            max_age: Duration::from_secs(1),
+        };
+        let files = [
+            file(1000, 100, 10, Some(("a", "b"))),
+            file(1500, 100, 10, Some(("a", "b"))),
+            file(500, 100, 0, Some(("a", "b"))),
+        ];
+        assert_eq!(policy.select(&files, 2200), vec![1000]);
+    }
+
+    #[test]
+    fn key_range_selects_overlapping_files() {
+        let files = [
+            file(1, 100, 0, Some(("a", "c"))),
+            file(2, 100, 0, Some(("d", "f"))),
+            file(3, 100, 0, Some(("g", "k"))),
+            file(4, 100, 100, None),
+        ];
+        let policy = KeyRange {
+            start: b"c".to_vec(),
+            end: Some(b"g".to_vec()),
+        };
+        assert_eq!(policy.select(&files, 0), vec![1, 2, 4]);
+        let policy = KeyRange {
+            start: b"e".to_vec(),
+            end: None,
+        };
+        assert_eq!(policy.select(&files, 0), vec![2, 3, 4]);
+    }
+}
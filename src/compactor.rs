@@ -0,0 +1,161 @@
+use errors::Error;
+use index::Log;
+use options::Options;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// 每隔多少次写入检查一次待压缩空间的比例
+const CHECK_WRITES: u64 = 256;
+// 检查前台是否仍在写入的间隔
+const YIELD_INTERVAL: Duration = Duration::from_millis(10);
+// 每个文件压缩前最多为前台写入等待的时间,保证压缩总能推进
+const MAX_YIELD: Duration = Duration::from_secs(1);
+
+// 后台压缩线程
+// 定时或在待压缩空间超过阈值时唤醒,每压缩一个文件释放一次写锁
+// 压缩前等待前台写入停歇,并按compaction_rate限制读取速度
+#[derive(Debug)]
+pub struct Compactor {
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    wakeup: Condvar,
+    // 前台写入次数
+    writes: AtomicU64,
+    interval: Duration,
+    threshold: f32,
+    rate: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // 数据库关闭,线程应当退出
+    stop: bool,
+    // 写入较多,需要检查待压缩空间的比例
+    dirty: bool,
+}
+
+impl Compactor {
+    // 启动压缩线程,线程持有log直到stop
+    pub fn start(log: Arc<RwLock<Log<'static>>>, options: &Options) -> Result<Compactor, Error> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            wakeup: Condvar::new(),
+            writes: AtomicU64::new(0),
+            interval: options.compaction_interval,
+            threshold: options.compaction_threshold,
+            rate: options.compaction_rate,
+        });
+        let handle = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("koundb-compactor".to_string())
+                .spawn(move || shared.run(&log))?
+        };
+        Ok(Compactor {
+            shared,
+            handle: Some(handle),
+        })
+    }
+    // 前台完成一次写入
+    pub fn wrote(&self) {
+        let writes = self.shared.writes.fetch_add(1, Ordering::Relaxed) + 1;
+        if writes.is_multiple_of(CHECK_WRITES) {
+            self.shared.state.lock().unwrap().dirty = true;
+            self.shared.wakeup.notify_one();
+        }
+    }
+    // 通知线程退出并等待,正在压缩的文件会先完成
+    pub fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.shared.state.lock().unwrap().stop = true;
+            self.shared.wakeup.notify_one();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Compactor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Shared {
+    fn run(&self, log: &RwLock<Log<'static>>) {
+        loop {
+            let timer = {
+                let state = self.state.lock().unwrap();
+                let (mut state, timeout) = self
+                    .wakeup
+                    .wait_timeout_while(state, self.interval, |state| !state.stop && !state.dirty)
+                    .unwrap();
+                if state.stop {
+                    return;
+                }
+                state.dirty = false;
+                timeout.timed_out()
+            };
+            if timer || log.read().unwrap().dead_ratio() >= self.threshold {
+                // 出错时放弃本轮压缩,下次唤醒时重试
+                let _ = self.compact(log);
+            }
+        }
+    }
+    // 逐个文件压缩,文件之间释放写锁
+    fn compact(&self, log: &RwLock<Log<'static>>) -> Result<(), Error> {
+        let mut compaction = log.write().unwrap().compaction()?;
+        loop {
+            if !self.yield_to_writes() {
+                return Ok(());
+            }
+            let start = Instant::now();
+            let read = match log.write().unwrap().compact_next(&mut compaction)? {
+                Some(read) => read,
+                None => return Ok(()),
+            };
+            if !self.throttle(read, start.elapsed()) {
+                return Ok(());
+            }
+        }
+    }
+    // 前台仍在写入时等待,最多等待MAX_YIELD,数据库关闭时返回false
+    fn yield_to_writes(&self) -> bool {
+        let deadline = Instant::now() + MAX_YIELD;
+        loop {
+            let seen = self.writes.load(Ordering::Relaxed);
+            if !self.pause(YIELD_INTERVAL) {
+                return false;
+            }
+            if self.writes.load(Ordering::Relaxed) == seen || Instant::now() >= deadline {
+                return true;
+            }
+        }
+    }
+    // 读取read字节用时elapsed,按rate补足等待时间,数据库关闭时返回false
+    fn throttle(&self, read: u32, elapsed: Duration) -> bool {
+        if self.rate == 0 {
+            return true;
+        }
+        let budget = Duration::from_secs_f64(f64::from(read) / self.rate as f64);
+        match budget.checked_sub(elapsed) {
+            Some(wait) => self.pause(wait),
+            None => true,
+        }
+    }
+    // 等待duration,数据库关闭时提前返回false
+    fn pause(&self, duration: Duration) -> bool {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self
+            .wakeup
+            .wait_timeout_while(state, duration, |state| !state.stop)
+            .unwrap();
+        !state.stop
+    }
+}
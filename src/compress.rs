@@ -0,0 +1,42 @@
+use errors::Error;
+use lz4_flex::block::{compress_prepend_size, decompress_size_prepended};
+
+// value的压缩算法,在打开数据库时选择
+// 每条记录单独标记所用算法,切换算法后旧记录仍可读取
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None = 0,
+    Lz4 = 1,
+}
+
+impl Compression {
+    pub fn from_u8(codec: u8) -> Option<Compression> {
+        match codec {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            _ => None,
+        }
+    }
+
+    // 压缩value,压缩后没有变小时返回None
+    pub fn compress(self, value: &[u8]) -> Option<Vec<u8>> {
+        let compressed = match self {
+            Compression::None => return None,
+            Compression::Lz4 => compress_prepend_size(value),
+        };
+        if compressed.len() < value.len() {
+            Some(compressed)
+        } else {
+            None
+        }
+    }
+
+    pub fn decompress(self, value: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Compression::None => Ok(value.to_vec()),
+            Compression::Lz4 => decompress_size_prepended(value)
+                .map_err(|err| Error::InvalidFormat(format!("bad lz4 value: {}", err))),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::fmt;
+
+pub const NONCE_SIZE: usize = 12;
+pub const TAG_SIZE: usize = 16;
+// 加密后增加的长度
+pub const OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+// ChaCha20-Poly1305加密,每次加密使用随机nonce
+// 密文格式: nonce(12) ciphertext tag(16)
+#[derive(Clone)]
+pub struct Cipher {
+    aead: ChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub fn new(key: &[u8; 32]) -> Cipher {
+        Cipher {
+            aead: ChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+
+    // 加密plaintext,aad参与认证但不加密
+    pub fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .aead
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .expect("plaintext is too long");
+        let mut sealed = Vec::with_capacity(OVERHEAD + plaintext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend(ciphertext);
+        sealed
+    }
+
+    // 解密seal的结果,密钥错误或内容被篡改时返回None
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < OVERHEAD {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        self.aead
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .ok()
+    }
+}
+
+// 不打印密钥
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Cipher { .. }")
+    }
+}
@@ -1,18 +1,271 @@
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use compress::Compression;
+use crc32c::{crc32c, crc32c_append};
+use crypto::{self, Cipher};
 use errors::Error;
-use filepool::FilePool;
+use filepool::{FilePool, FileTable, MAX_FILESIZE};
 use std::borrow::Cow;
-use std::collections::HashMap;
-use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Cursor, Read, Write};
 use std::sync::{Arc, Mutex};
-use util::{roundup, Timestamp};
+use util::{read_exact_at, roundup, write_all_at, Timestamp};
+use wal::{Wal, WalEntry};
+
+// wal超过该大小时同步.data文件并清空wal
+const MAX_WALSIZE: u64 = 1 << 22;
+// 记录头: crc(4) keysize(2) kind(1) flags(1) valuesize(4) time(8) expiry(8)
+// crc为记录头其余部分和记录内容的crc32c校验和
+// flags低4位为value的压缩算法,valuesize为压缩后的大小
+// flags最高位表示key和value被加密为nonce(12) ciphertext tag(16),记录头作为附加认证数据
+pub const RECORD_HEADER_SIZE: usize = 4 + 2 + 1 + 1 + 4 + 8 + 8;
+const CODEC_MASK: u8 = 0x0f;
+const FLAG_ENCRYPTED: u8 = 0x80;
+// expiry为0的记录永不过期
+pub const NEVER_EXPIRE: Timestamp = 0;
+// 记录按16字节对齐分配
+pub const ALIGNMENT: usize = 16;
+
+// 文件头: magic(6) kind(1) version(1) fileid(8) alignment(4) maxsize(4) crc(4)
+// 填充至32字节,记录的偏移从文件头之后开始计算
+pub const FILE_HEADER_SIZE: u64 = 32;
+const MAGIC: &[u8; 6] = b"KOUNDB";
+// 文件格式版本,格式变化时递增
+pub const FORMAT_VERSION: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    Data = 1,
+    Index = 2,
+    Wal = 3,
+}
+
+// .data和.index文件的文件头
+#[derive(Debug, Clone)]
+pub struct FileHeader {
+    pub kind: FileKind,
+    pub version: u8,
+    pub fileid: Timestamp,
+    pub alignment: u32,
+    pub maxsize: u32,
+}
+
+impl FileHeader {
+    pub fn new(kind: FileKind, fileid: Timestamp) -> FileHeader {
+        FileHeader {
+            kind,
+            version: FORMAT_VERSION,
+            fileid,
+            alignment: ALIGNMENT as u32,
+            maxsize: MAX_FILESIZE,
+        }
+    }
+    // 读取文件头,magic或校验和不匹配时返回Error::InvalidFormat
+    pub fn read_from<R>(reader: &mut R) -> Result<FileHeader, Error>
+    where
+        R: Read,
+    {
+        let mut buf = [0; FILE_HEADER_SIZE as usize];
+        reader.read_exact(&mut buf)?;
+        if &buf[0..6] != MAGIC {
+            return Err(Error::InvalidFormat("bad magic number".to_string()));
+        }
+        if LittleEndian::read_u32(&buf[24..28]) != crc32c(&buf[0..24]) {
+            return Err(Error::InvalidFormat(
+                "file header checksum mismatch".to_string(),
+            ));
+        }
+        let kind = match buf[6] {
+            1 => FileKind::Data,
+            2 => FileKind::Index,
+            3 => FileKind::Wal,
+            kind => return Err(Error::InvalidFormat(format!("unknown file kind {}", kind))),
+        };
+        Ok(FileHeader {
+            kind,
+            version: buf[7],
+            fileid: LittleEndian::read_u64(&buf[8..16]),
+            alignment: LittleEndian::read_u32(&buf[16..20]),
+            maxsize: LittleEndian::read_u32(&buf[20..24]),
+        })
+    }
+    pub fn write_bytes<W>(&self, writer: &mut W) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        let mut buf = [0; FILE_HEADER_SIZE as usize];
+        buf[0..6].copy_from_slice(MAGIC);
+        buf[6] = self.kind as u8;
+        buf[7] = self.version;
+        LittleEndian::write_u64(&mut buf[8..16], self.fileid);
+        LittleEndian::write_u32(&mut buf[16..20], self.alignment);
+        LittleEndian::write_u32(&mut buf[20..24], self.maxsize);
+        let crc = crc32c(&buf[0..24]);
+        LittleEndian::write_u32(&mut buf[24..28], crc);
+        writer.write_all(&buf)?;
+        Ok(())
+    }
+    // 检查文件头与期望的文件及当前格式一致
+    pub fn check(&self, kind: FileKind, fileid: Timestamp) -> Result<(), Error> {
+        let expected = FileHeader::new(kind, fileid);
+        if self.kind != expected.kind {
+            Err(Error::InvalidFormat(format!(
+                "file {} is {:?} file, expect {:?} file",
+                fileid, self.kind, kind
+            )))
+        } else if self.version != expected.version {
+            Err(Error::InvalidFormat(format!(
+                "file {} has format version {}, expect {}",
+                fileid, self.version, expected.version
+            )))
+        } else if self.fileid != expected.fileid {
+            Err(Error::InvalidFormat(format!(
+                "file {} has fileid {} in header",
+                fileid, self.fileid
+            )))
+        } else if self.alignment != expected.alignment || self.maxsize != expected.maxsize {
+            Err(Error::InvalidFormat(format!(
+                "file {} has alignment {} and max size {}, expect {} and {}",
+                fileid, self.alignment, self.maxsize, expected.alignment, expected.maxsize
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// expiry为NEVER_EXPIRE时永不过期,否则在now达到expiry时过期
+pub fn is_expired(expiry: Timestamp, now: Timestamp) -> bool {
+    expiry != NEVER_EXPIRE && expiry <= now
+}
+
+// 记录类型
+// Put: 写入key-value
+// Delete: 删除key,覆盖在被删除的记录上,只保存key,原记录其余的空间被清零
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordKind {
+    Put = 1,
+    Delete = 2,
+}
+
+impl RecordKind {
+    fn from_u8(kind: u8) -> Option<RecordKind> {
+        match kind {
+            1 => Some(RecordKind::Put),
+            2 => Some(RecordKind::Delete),
+            _ => None,
+        }
+    }
+}
+
+// 解析后的记录头
+struct RecordHeader {
+    crc: u32,
+    keysize: usize,
+    kind: RecordKind,
+    compression: Compression,
+    encrypted: bool,
+    valuesize: usize,
+    time: Timestamp,
+    expiry: Timestamp,
+}
+
+impl RecordHeader {
+    // 解析记录头,keysize为0时返回None,字段非法时返回Error::Corruption
+    fn parse(header: &[u8], fileid: Timestamp, offset: u32) -> Result<Option<RecordHeader>, Error> {
+        let crc = LittleEndian::read_u32(&header[0..4]);
+        let keysize = LittleEndian::read_u16(&header[4..6]) as usize;
+        if keysize == 0 {
+            return Ok(None);
+        }
+        let flags = header[7];
+        let valuesize = LittleEndian::read_u32(&header[8..12]);
+        let time = LittleEndian::read_u64(&header[12..20]);
+        let expiry = LittleEndian::read_u64(&header[20..28]);
+        let (kind, compression) = match (
+            RecordKind::from_u8(header[6]),
+            Compression::from_u8(flags & CODEC_MASK),
+        ) {
+            (Some(kind), Some(compression))
+                if valuesize <= MAX_FILESIZE && flags & !(CODEC_MASK | FLAG_ENCRYPTED) == 0 =>
+            {
+                (kind, compression)
+            }
+            _ => return Err(Error::Corruption { fileid, offset }),
+        };
+        Ok(Some(RecordHeader {
+            crc,
+            keysize,
+            kind,
+            compression,
+            encrypted: flags & FLAG_ENCRYPTED != 0,
+            valuesize: valuesize as usize,
+            time,
+            expiry,
+        }))
+    }
+    // 记录头之后的内容大小
+    fn bodysize(&self) -> usize {
+        let overhead = if self.encrypted { crypto::OVERHEAD } else { 0 };
+        self.keysize + self.valuesize + overhead
+    }
+    // 校验并解密记录内容,未加密时key和value沿用body的借用
+    fn into_record<'a>(
+        self,
+        header: &[u8],
+        body: Cow<'a, [u8]>,
+        fileid: Timestamp,
+        offset: u32,
+        cipher: Option<&Cipher>,
+    ) -> Result<Record<'a>, Error> {
+        if crc32c_append(crc32c(&header[4..]), &body) != self.crc {
+            return Err(Error::Corruption { fileid, offset });
+        }
+        let body = if self.encrypted {
+            match cipher.and_then(|cipher| cipher.open(&body, &header[4..])) {
+                Some(plaintext) => Cow::from(plaintext),
+                None => return Err(Error::Decryption { fileid, offset }),
+            }
+        } else {
+            body
+        };
+        let (key, value) = match body {
+            Cow::Borrowed(body) => (
+                Cow::from(&body[..self.keysize]),
+                Cow::from(&body[self.keysize..]),
+            ),
+            Cow::Owned(mut body) => {
+                let value = body.split_off(self.keysize);
+                (Cow::from(body), Cow::from(value))
+            }
+        };
+        Ok(Record {
+            kind: self.kind,
+            key,
+            value,
+            time: self.time,
+            expiry: self.expiry,
+            compression: self.compression,
+            encrypted: self.encrypted,
+        })
+    }
+}
+
 // .data 文件中的记录结构
 // key和value的应当大于u32
+// expiry: 过期时间,与time同为毫秒时间戳
+// value: 文件中保存的value,按compression压缩
+// encrypted: 写入文件时是否加密key和value
 #[derive(Debug, Clone)]
 pub struct Record<'a> {
+    pub kind: RecordKind,
     pub key: Cow<'a, [u8]>,
     pub value: Cow<'a, [u8]>,
     pub time: Timestamp,
+    pub expiry: Timestamp,
+    pub compression: Compression,
+    pub encrypted: bool,
 }
 
 impl<'a> Record<'a> {
@@ -24,98 +277,249 @@ impl<'a> Record<'a> {
         let key = Cow::from(key);
         let value = Cow::from(value);
         Record {
-            key: key,
-            value: value,
-            time: time,
+            kind: RecordKind::Put,
+            key,
+            value,
+            time,
+            expiry: NEVER_EXPIRE,
+            compression: Compression::None,
+            encrypted: false,
         }
     }
-    pub fn size(&self) -> usize {
-        2 + 4 + 8 + self.key.len() + self.value.len()
+    // 用compression压缩value,压缩后没有变小时保持原样
+    pub fn compress(mut self, compression: Compression) -> Record<'a> {
+        if self.compression == Compression::None {
+            if let Some(value) = compression.compress(&self.value) {
+                self.value = Cow::from(value);
+                self.compression = compression;
+            }
+        }
+        self
     }
-
-    pub fn read_from<R>(reader: &mut R) -> Result<Option<Record<'a>>, Error>
+    // 解压后的value
+    pub fn into_value(self) -> Result<Vec<u8>, Error> {
+        match self.compression {
+            Compression::None => Ok(self.value.into_owned()),
+            compression => compression.decompress(&self.value),
+        }
+    }
+    // 解压后的value,未压缩时借用记录中的value
+    pub fn value(&self) -> Result<Cow<'_, [u8]>, Error> {
+        match self.compression {
+            Compression::None => Ok(Cow::from(&self.value[..])),
+            compression => compression.decompress(&self.value).map(Cow::from),
+        }
+    }
+    // 只有记录头和key的删除记录,不读取被删除的记录
+    pub fn tombstone<K>(key: K, time: Timestamp, encrypted: bool) -> Record<'a>
     where
-        R: Read + Seek,
+        Cow<'a, [u8]>: From<K>,
     {
-        let keysize = reader.read_u16::<LittleEndian>()?;
-        if keysize == 0 {
-            return Ok(None);
+        Record {
+            kind: RecordKind::Delete,
+            key: Cow::from(key),
+            value: Cow::Owned(Vec::new()),
+            time,
+            expiry: NEVER_EXPIRE,
+            compression: Compression::None,
+            encrypted,
         }
-        let valuesize = reader.read_u32::<LittleEndian>()?;
-        let time = reader.read_u64::<LittleEndian>()?;
-        let mut keybuf = vec![0; keysize as usize];
-        reader.read_exact(&mut keybuf)?;
-        let mut valuebuf = vec![0; valuesize as usize];
-        reader.read_exact(&mut valuebuf)?;
-        let key = Cow::from(keybuf);
-        let value = Cow::from(valuebuf);
-        Ok(Some(Record {
-            key: key,
-            value: value,
-            time: time,
-        }))
+    }
+    // 保留原value的删除记录,与原记录占用相同的空间
+    // 只用于快照仍会读取的记录,快照释放后改写为tombstone
+    pub fn tombstone_with_value(&self, time: Timestamp) -> Record<'a> {
+        Record {
+            kind: RecordKind::Delete,
+            key: Cow::from(self.key.to_vec()),
+            value: Cow::from(self.value.to_vec()),
+            time,
+            expiry: self.expiry,
+            compression: self.compression,
+            encrypted: self.encrypted,
+        }
+    }
+    // value是否直接借用映射的.data文件且未压缩,读取时无需复制或解码
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.value, Cow::Borrowed(_)) && self.compression == Compression::None
+    }
+    // 在now时记录是否已过期
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        is_expired(self.expiry, now)
+    }
+    pub fn size(&self) -> usize {
+        let overhead = if self.encrypted { crypto::OVERHEAD } else { 0 };
+        RECORD_HEADER_SIZE + self.key.len() + self.value.len() + overhead
+    }
+    // 记录在文件中的分配大小
+    pub fn allocsize(&self) -> u32 {
+        roundup(self.size(), ALIGNMENT) as u32
     }
 
-    fn to_bytes(&self) -> Result<Vec<u8>, Error> {
-        let allocsize = roundup(self.size(), 16);
+    // 读取fileid文件offset处的记录,keysize为0时返回None
+    // 校验和不匹配或记录不完整时返回Error::Corruption
+    // 加密记录无法用cipher解密时返回Error::Decryption
+    pub fn read_from(
+        file: &File,
+        fileid: Timestamp,
+        offset: u32,
+        cipher: Option<&Cipher>,
+    ) -> Result<Option<Record<'a>>, Error> {
+        let position = FILE_HEADER_SIZE + offset as u64;
+        let mut header = [0; RECORD_HEADER_SIZE];
+        read_exact_at(file, &mut header, position)?;
+        let parsed = match RecordHeader::parse(&header, fileid, offset)? {
+            Some(parsed) => parsed,
+            None => return Ok(None),
+        };
+        let mut body = vec![0; parsed.bodysize()];
+        // 记录头完整而内容不完整,说明记录已损坏
+        match read_exact_at(file, &mut body, position + RECORD_HEADER_SIZE as u64) {
+            Ok(()) => {}
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(Error::Corruption { fileid, offset })
+            }
+            Err(err) => return Err(Error::Io(err)),
+        }
+        parsed
+            .into_record(&header, Cow::from(body), fileid, offset, cipher)
+            .map(Some)
+    }
+    // 从映射的整个.data文件中读取offset处的记录,未加密的key和value直接借用map
+    // 记录超出map范围时返回UnexpectedEof,调用方应改用read_from读取
+    pub fn read_from_map(
+        map: &'a [u8],
+        fileid: Timestamp,
+        offset: u32,
+        cipher: Option<&Cipher>,
+    ) -> Result<Option<Record<'a>>, Error> {
+        let position = FILE_HEADER_SIZE as usize + offset as usize;
+        let outside = || Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof));
+        let header = map
+            .get(position..position + RECORD_HEADER_SIZE)
+            .ok_or_else(outside)?;
+        let parsed = match RecordHeader::parse(header, fileid, offset)? {
+            Some(parsed) => parsed,
+            None => return Ok(None),
+        };
+        let start = position + RECORD_HEADER_SIZE;
+        let body = map
+            .get(start..start + parsed.bodysize())
+            .ok_or_else(outside)?;
+        parsed
+            .into_record(header, Cow::from(body), fileid, offset, cipher)
+            .map(Some)
+    }
+
+    fn to_bytes(&self, cipher: Option<&Cipher>) -> Result<Vec<u8>, Error> {
+        let allocsize = roundup(self.size(), ALIGNMENT);
         let mut buf = Cursor::new(Vec::with_capacity(allocsize));
+        let mut flags = self.compression as u8;
+        if self.encrypted {
+            flags |= FLAG_ENCRYPTED;
+        }
+        buf.write_u32::<LittleEndian>(0)?;
         buf.write_u16::<LittleEndian>(self.key.len() as u16)?;
+        buf.write_u8(self.kind as u8)?;
+        buf.write_u8(flags)?;
         buf.write_u32::<LittleEndian>(self.value.len() as u32)?;
         buf.write_u64::<LittleEndian>(self.time)?;
-        buf.write_all(&self.key)?;
-        buf.write_all(&self.value)?;
-        Ok(buf.into_inner())
-    }
-    fn write_bytes<W>(&self, writer: &mut W) -> Result<(), Error>
-    where
-        W: Write + Seek,
-    {
-        let buf: Vec<u8> = self.to_bytes()?;
-        writer.write_all(&buf)?;
-        Ok(())
+        buf.write_u64::<LittleEndian>(self.expiry)?;
+        let mut buf = buf.into_inner();
+        if self.encrypted {
+            let cipher = match cipher {
+                Some(cipher) => cipher,
+                None => {
+                    return Err(Error::InvalidFormat(
+                        "no encryption key for encrypted record".to_string(),
+                    ))
+                }
+            };
+            let mut plaintext = Vec::with_capacity(self.key.len() + self.value.len());
+            plaintext.extend_from_slice(&self.key);
+            plaintext.extend_from_slice(&self.value);
+            let sealed = cipher.seal(&plaintext, &buf[4..]);
+            buf.extend(sealed);
+        } else {
+            buf.extend_from_slice(&self.key);
+            buf.extend_from_slice(&self.value);
+        }
+        let crc = crc32c(&buf[4..]);
+        LittleEndian::write_u32(&mut buf[0..4], crc);
+        Ok(buf)
     }
 }
+// 一个.data文件中的所有有效记录
+// records: (记录偏移,记录)
+// corrupt: 损坏记录占用的(偏移,大小)
 #[derive(Debug)]
 pub struct Recordfile<'a> {
-    pub fileid: Timestamp,
-    pub size: u32,
-    pub records: Vec<Record<'a>>,
+    pub records: Vec<(u32, Record<'a>)>,
+    pub corrupt: Vec<(u32, u32)>,
 }
 impl<'a> Recordfile<'a> {
-    pub fn read_from<R>(
-        reader: &mut R,
+    // 以ALIGNMENT为单位扫描文件,keysize为0或记录头非法的位置为空闲空间
+    // 返回Put和Delete记录,文件末尾不完整的记录头被忽略
+    // 记录头合法而校验和不匹配或内容不完整的记录已损坏,记入corrupt
+    // 损坏记录的大小取记录头中的大小,截止到下一个有效记录
+    // 加密记录无法解密说明密钥错误,返回Error::Decryption
+    pub fn read_from(
+        file: &File,
         fileid: Timestamp,
         endoff: u32,
-    ) -> Result<Recordfile<'a>, Error>
-    where
-        R: Read + Seek,
-    {
-        let mut records: Vec<Record<'a>> = Vec::new();
+        cipher: Option<&Cipher>,
+    ) -> Result<Recordfile<'a>, Error> {
+        let mut records: Vec<(u32, Record<'a>)> = Vec::new();
+        let mut corrupt = Vec::new();
+        // 正在扫描的损坏记录的(起点,终点)
+        let mut damaged: Option<(u32, u32)> = None;
         let mut off = 0;
-        let mut size = 0;
-        loop {
-            let rec = Record::read_from(reader)?;
-            match rec {
-                None => off += 2,
-                Some(record) => {
-                    let recsize = record.size();
-                    let allocsize = roundup(recsize, 16);
-                    off += allocsize;
-                    size += allocsize;
-                    // 跳过分配距离
-                    reader.seek(SeekFrom::Start(off as u64))?;
-                    records.push(record);
+        while off < endoff {
+            if let Some((start, end)) = damaged {
+                if off >= end {
+                    corrupt.push((start, end - start));
+                    damaged = None;
                 }
             }
-            if off >= endoff as usize {
-                break;
+            let mut header = [0; RECORD_HEADER_SIZE];
+            match read_exact_at(file, &mut header, FILE_HEADER_SIZE + u64::from(off)) {
+                Ok(()) => {}
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(Error::Io(err)),
+            }
+            let parsed = match RecordHeader::parse(&header, fileid, off) {
+                Ok(Some(parsed)) => parsed,
+                Ok(None) | Err(Error::Corruption { .. }) => {
+                    off += ALIGNMENT as u32;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            match Record::read_from(file, fileid, off, cipher) {
+                Ok(Some(record)) => {
+                    if let Some((start, _)) = damaged.take() {
+                        corrupt.push((start, off - start));
+                    }
+                    let allocsize = record.allocsize();
+                    records.push((off, record));
+                    // 跳过分配距离
+                    off += allocsize;
+                }
+                Ok(None) => off += ALIGNMENT as u32,
+                Err(Error::Corruption { .. }) => {
+                    if damaged.is_none() {
+                        let allocsize = roundup(RECORD_HEADER_SIZE + parsed.bodysize(), ALIGNMENT);
+                        damaged = Some((off, off + allocsize as u32));
+                    }
+                    off += ALIGNMENT as u32;
+                }
+                Err(err) => return Err(err),
             }
         }
-        Ok(Recordfile {
-            fileid: fileid,
-            size: size as u32,
-            records: records,
-        })
+        if let Some((start, end)) = damaged {
+            let end = end.min(roundup(endoff as usize, ALIGNMENT) as u32);
+            corrupt.push((start, end - start));
+        }
+        Ok(Recordfile { records, corrupt })
     }
 }
 
@@ -123,148 +527,340 @@ impl<'a> Recordfile<'a> {
 pub struct RecordWriter<'a> {
     // datafile的句柄池
     filepool: Arc<Mutex<FilePool>>,
+    // 与读者共享的句柄表
+    table: Arc<FileTable>,
     // 所有待写Record的hashmap
     recordmap: HashMap<u64, Vec<(u32, Record<'a>)>>,
+    // 待清零的空间, 文件id -> (偏移,大小)
+    erasemap: HashMap<u64, Vec<(u32, u32)>>,
+    // 预写日志
+    wal: Wal,
+    // 已写入但尚未同步的文件
+    dirtyfiles: HashSet<Timestamp>,
 }
 
 impl<'a> RecordWriter<'a> {
-    pub fn new(filepool: Arc<Mutex<FilePool>>) -> RecordWriter<'a> {
-        RecordWriter {
-            filepool: filepool,
+    pub fn open(filepool: Arc<Mutex<FilePool>>) -> Result<RecordWriter<'a>, Error> {
+        let (wal, table) = {
+            let filepool = filepool.lock().unwrap();
+            (Wal::new(filepool.open_walfile()?)?, filepool.table())
+        };
+        Ok(RecordWriter {
+            filepool,
+            table,
             recordmap: HashMap::new(),
-        }
+            erasemap: HashMap::new(),
+            wal,
+            dirtyfiles: HashSet::new(),
+        })
     }
     // 得到待写文件的偏移
     pub fn get_offset(&mut self, record: &Record) -> Result<(u64, u32), Error> {
-        let size = roundup(record.size(), 16);
-        let (fileid, offset) = self.filepool
+        let (fileid, offset) = self
+            .filepool
             .lock()
             .unwrap()
-            .request_room_ornew(size as u32)?;
+            .request_room_ornew(record.allocsize())?;
         Ok((fileid, offset))
     }
-    // 释放记录文件空间
-    pub fn free_record(
-        &mut self,
-        record: &Record,
-        fileid: Timestamp,
-        offset: u32,
-    ) -> Result<(), Error> {
-        let size = roundup(record.size(), 16);
-        self.filepool
-            .lock()
-            .unwrap()
-            .free_room(size as u32, offset, fileid)
-    }
     // 插入一个待写record
-    // FIXME:ERROR
     pub fn insert_record(
         &mut self,
         fileid: u64,
         offset: u32,
         record: Record<'a>,
     ) -> Result<(), Error> {
-        if self.recordmap.contains_key(&fileid) {
-            self.recordmap.insert(fileid, vec![(offset, record)]);
-        } else {
-            self.recordmap
-                .get_mut(&fileid)
-                .unwrap()
-                .push((offset, record));
-        }
+        self.recordmap
+            .entry(fileid)
+            .or_default()
+            .push((offset, record));
         Ok(())
     }
+    // 清零fileid文件offset处size大小的空间,扫描时视为空闲空间
+    pub fn erase(&mut self, fileid: u64, offset: u32, size: u32) {
+        self.erasemap
+            .entry(fileid)
+            .or_default()
+            .push((offset, size));
+    }
     // 写全部map中的记录,并将map清空
+    // 记录先作为一批写入wal并同步,之后才写.data文件
     pub fn write_all(&mut self, sync_now: bool) -> Result<(), Error> {
+        let entries = self.commit()?;
+        self.apply_committed(&entries, sync_now)
+    }
+    // 将map中的记录作为一批写入wal并同步,并将map清空,返回这批写入
+    // 失败时这批写入没有进入wal,重新打开时不会被重放
+    pub fn commit(&mut self) -> Result<Vec<WalEntry>, Error> {
+        let mut entries = Vec::new();
+        if self.recordmap.is_empty() && self.erasemap.is_empty() {
+            return Ok(entries);
+        }
+        let table = self.table.clone();
+        let cipher = table.cipher();
         for (fileid, recordlist) in self.recordmap.drain() {
-            let mut file = self.filepool.lock().unwrap().get_file(fileid)?;
+            // 文件即将被修改,旧的.index文件失效
+            self.filepool.lock().unwrap().invalidate_indexfile(fileid)?;
             for (offset, record) in recordlist.iter() {
-                file.seek(SeekFrom::Start(*offset as u64))?;
-                record.write_bytes(&mut file)?;
-                if sync_now {
-                    file.sync_all()?;
-                }
+                entries.push(WalEntry {
+                    fileid,
+                    offset: *offset,
+                    bytes: record.to_bytes(cipher)?,
+                });
             }
         }
+        for (fileid, erased) in self.erasemap.drain() {
+            self.filepool.lock().unwrap().invalidate_indexfile(fileid)?;
+            for (offset, size) in erased {
+                entries.push(WalEntry {
+                    fileid,
+                    offset,
+                    bytes: vec![0; size as usize],
+                });
+            }
+        }
+        self.wal.append(&entries)?;
+        Ok(entries)
+    }
+    // 将commit返回的写入应用到.data文件
+    // 失败时这批写入已在wal中,重新打开时会被重放
+    pub fn apply_committed(&mut self, entries: &[WalEntry], sync_now: bool) -> Result<(), Error> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        self.apply(entries, sync_now)?;
+        if self.wal.size() > MAX_WALSIZE {
+            self.sync_all()?;
+        }
         Ok(())
     }
-    // 根据map的记录,写索引
-    pub fn write_allIndex(&mut self, sync_now: bool) -> Result<(), Error> {
-        for (fileid, recordlist) in self.recordmap.iter() {
-            let mut file = self.filepool.lock().unwrap().get_indexfile(*fileid)?;
-            for (offset, record) in recordlist.iter() {
-                let index = Index::new(record, *offset);
-                index.write_bytes(&mut file)?;
-                if sync_now {
-                    file.sync_all()?;
-                }
+    // 按顺序将写入应用到.data文件
+    fn apply(&mut self, entries: &[WalEntry], sync_now: bool) -> Result<(), Error> {
+        let mut files: HashMap<Timestamp, Arc<File>> = HashMap::new();
+        // 写入期间快照与迭代器不能读取记录
+        {
+            let _writing = self.table.iogate().write().unwrap();
+            for entry in entries {
+                let file = match files.entry(entry.fileid) {
+                    Entry::Occupied(file) => file.into_mut(),
+                    Entry::Vacant(slot) => slot.insert(self.table.get_file(entry.fileid)?),
+                };
+                write_all_at(file, &entry.bytes, FILE_HEADER_SIZE + entry.offset as u64)?;
+            }
+        }
+        for (fileid, file) in files {
+            if sync_now {
+                file.sync_all()?;
+            } else {
+                self.dirtyfiles.insert(fileid);
             }
         }
         Ok(())
     }
+    // 同步所有已写入的.data文件,之后wal中的内容不再需要
+    pub fn sync_all(&mut self) -> Result<(), Error> {
+        for fileid in self.dirtyfiles.drain() {
+            let filepool = self.filepool.lock().unwrap();
+            // 文件可能已被压缩删除
+            if !filepool.has_file(fileid) {
+                continue;
+            }
+            filepool.get_file(fileid)?.sync_all()?;
+        }
+        self.wal.truncate()
+    }
+    // 打开时重放wal中完整的写入,之后清空wal
+    pub fn replay(&mut self) -> Result<(), Error> {
+        let mut entries = self.wal.read_all()?;
+        {
+            let filepool = self.filepool.lock().unwrap();
+            entries.retain(|entry| filepool.has_file(entry.fileid));
+            // 崩溃时.index文件可能尚未删除
+            for entry in entries.iter() {
+                if filepool.has_indexfile(entry.fileid) {
+                    filepool.removefile_withid(entry.fileid, false)?;
+                }
+            }
+        }
+        self.apply(&entries, true)?;
+        self.wal.truncate()
+    }
 }
 
+// .index文件中的索引项,记录一条存活记录在.data文件中的位置
+// size: 记录的分配大小
 #[derive(Debug, Clone)]
-struct Index {
-    keysize: u16,
-    valuesize: u32,
-    offset: u32,
-    time: Timestamp,
+pub struct Index {
+    pub kind: RecordKind,
+    pub key: Vec<u8>,
+    pub offset: u32,
+    pub size: u32,
+    pub time: Timestamp,
+    pub expiry: Timestamp,
 }
 impl Index {
-    fn new(record: &Record, offset: u32) -> Index
+    pub fn new(record: &Record, offset: u32) -> Index
 where {
-        let keysize = record.key.len();
-        let valuesize = record.value.len();
-        let time = record.time;
         Index {
-            keysize: keysize as u16,
-            valuesize: valuesize as u32,
-            offset: offset,
-            time: time,
+            kind: record.kind,
+            key: record.key.to_vec(),
+            offset,
+            size: record.allocsize(),
+            time: record.time,
+            expiry: record.expiry,
         }
     }
     #[inline]
     fn size(&self) -> usize {
-        2 + 4 + 4 + 8
+        2 + 1 + 4 + 4 + 8 + 8 + self.key.len()
     }
-    // 从indexfile中读取slot
-    fn read_from<R>(reader: &mut R) -> Result<Option<Index>, Error>
+    // 从indexfile中读取索引项
+    fn read_from<R>(reader: &mut R) -> Result<Index, Error>
     where
-        R: Read + Seek,
+        R: Read,
     {
         let keysize = reader.read_u16::<LittleEndian>()?;
-        let valuesize = reader.read_u32::<LittleEndian>()?;
-        if keysize == 0 || valuesize == 0 {
-            return Ok(None);
-        }
-        let mut keybuf = vec![0; keysize as usize];
-        reader.read_exact(&mut keybuf)?;
+        let kind = match RecordKind::from_u8(reader.read_u8()?) {
+            Some(kind) => kind,
+            None => return Err(Error::InvalidFormat("unknown record kind".to_string())),
+        };
+        let size = reader.read_u32::<LittleEndian>()?;
         let offset = reader.read_u32::<LittleEndian>()?;
         let time = reader.read_u64::<LittleEndian>()?;
-        Ok(Some(Index {
-            keysize: keysize,
-            valuesize: valuesize,
-            offset: offset,
-            time: time,
-        }))
+        let expiry = reader.read_u64::<LittleEndian>()?;
+        let mut key = vec![0; keysize as usize];
+        reader.read_exact(&mut key)?;
+        Ok(Index {
+            kind,
+            key,
+            offset,
+            size,
+            time,
+            expiry,
+        })
     }
-    // slot转化为vec<u8>
+    // 索引项转化为vec<u8>
     fn to_bytes(&self) -> Result<Vec<u8>, Error> {
         let mut buf = Cursor::new(Vec::with_capacity(self.size()));
-        buf.write_u16::<LittleEndian>(self.keysize)?;
-        buf.write_u32::<LittleEndian>(self.valuesize)?;
+        buf.write_u16::<LittleEndian>(self.key.len() as u16)?;
+        buf.write_u8(self.kind as u8)?;
+        buf.write_u32::<LittleEndian>(self.size)?;
         buf.write_u32::<LittleEndian>(self.offset)?;
         buf.write_u64::<LittleEndian>(self.time)?;
+        buf.write_u64::<LittleEndian>(self.expiry)?;
+        buf.write_all(&self.key)?;
         Ok(buf.into_inner())
     }
-    // slot写index文件
-    fn write_bytes<W>(&self, writer: &mut W) -> Result<(), Error>
+}
+
+// 一个.data文件对应的.index文件
+// datalen: 写入时.data文件的长度,与当前长度不一致说明.index已过期
+// 有密钥时文件头之后的内容整体加密,fileid作为附加认证数据
+#[derive(Debug)]
+pub struct Indexfile {
+    pub fileid: Timestamp,
+    pub datalen: u64,
+    pub indexes: Vec<Index>,
+}
+impl Indexfile {
+    pub fn read_from<R>(
+        reader: &mut R,
+        fileid: Timestamp,
+        cipher: Option<&Cipher>,
+    ) -> Result<Indexfile, Error>
     where
-        W: Write + Seek,
+        R: Read,
     {
-        let buf: Vec<u8> = self.to_bytes()?;
-        writer.write_all(&buf)?;
+        match cipher {
+            Some(cipher) => {
+                let mut sealed = Vec::new();
+                reader.read_to_end(&mut sealed)?;
+                match cipher.open(&sealed, &fileid.to_le_bytes()) {
+                    Some(plaintext) => Indexfile::parse(&mut Cursor::new(plaintext), fileid),
+                    None => Err(Error::Decryption { fileid, offset: 0 }),
+                }
+            }
+            None => Indexfile::parse(reader, fileid),
+        }
+    }
+    fn parse<R>(reader: &mut R, fileid: Timestamp) -> Result<Indexfile, Error>
+    where
+        R: Read,
+    {
+        let datalen = reader.read_u64::<LittleEndian>()?;
+        let count = reader.read_u32::<LittleEndian>()?;
+        let mut indexes = Vec::new();
+        for _ in 0..count {
+            indexes.push(Index::read_from(reader)?);
+        }
+        Ok(Indexfile {
+            fileid,
+            datalen,
+            indexes,
+        })
+    }
+    pub fn write_bytes<W>(&self, writer: &mut W, cipher: Option<&Cipher>) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        let mut buf = Vec::with_capacity(8 + 4);
+        buf.write_u64::<LittleEndian>(self.datalen)?;
+        buf.write_u32::<LittleEndian>(self.indexes.len() as u32)?;
+        for index in &self.indexes {
+            buf.extend(index.to_bytes()?);
+        }
+        match cipher {
+            Some(cipher) => writer.write_all(&cipher.seal(&buf, &self.fileid.to_le_bytes()))?,
+            None => writer.write_all(&buf)?,
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 模拟映射的.data文件: 文件头之后在偏移0处放置record
+    fn mapped(record: &Record, cipher: Option<&Cipher>) -> Vec<u8> {
+        let mut map = vec![0; FILE_HEADER_SIZE as usize];
+        map.extend(record.to_bytes(cipher).unwrap());
+        map
+    }
+
+    #[test]
+    fn read_from_map_borrows_plain_records() {
+        let map = mapped(&Record::new(&b"key"[..], &b"value"[..], 1), None);
+        let record = Record::read_from_map(&map, 1, 0, None).unwrap().unwrap();
+        assert!(matches!(record.key, Cow::Borrowed(b"key")));
+        assert!(matches!(record.value, Cow::Borrowed(b"value")));
+        assert!(matches!(record.value().unwrap(), Cow::Borrowed(b"value")));
+    }
+
+    #[test]
+    fn read_from_map_decrypts_into_owned() {
+        let cipher = Cipher::new(&[7; 32]);
+        let mut record = Record::new(&b"key"[..], &b"value"[..], 1);
+        record.encrypted = true;
+        let map = mapped(&record, Some(&cipher));
+        let record = Record::read_from_map(&map, 1, 0, Some(&cipher))
+            .unwrap()
+            .unwrap();
+        assert!(matches!(record.key, Cow::Owned(_)));
+        assert_eq!(&record.value[..], b"value");
+    }
+
+    #[test]
+    fn read_from_map_reports_records_past_the_end() {
+        let mut map = mapped(&Record::new(&b"key"[..], &b"value"[..], 1), None);
+        map.truncate(map.len() - 1);
+        match Record::read_from_map(&map, 1, 0, None) {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            Record::read_from_map(&map, 1, 1024, None),
+            Err(Error::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,244 @@
+use batch::WriteBatch;
+use compactor::Compactor;
+use crypto::Cipher;
+use data::NEVER_EXPIRE;
+use errors::Error;
+use filepool::FilePool;
+use index::{Iter, Log};
+use options::Options;
+use snapshot::Snapshot;
+use stats::Stats;
+use std::convert::TryFrom;
+use std::fs;
+use std::ops::Bound::{Included, Unbounded};
+use std::ops::RangeBounds;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+use transaction::{ReadSet, Transaction};
+use util::{check_key, get_timestamp, prefix_end, to_owned_bound, Timestamp};
+
+// 对外暴露的数据库句柄
+// 内部由Log维护索引,FilePool维护.data文件
+// Db可以在线程间共享,读取在读锁下并发进行,写入在写锁下串行进行
+#[derive(Debug)]
+pub struct Db {
+    log: Arc<RwLock<Log<'static>>>,
+    // 后台压缩线程,未开启后台压缩时为None
+    compactor: Option<Compactor>,
+}
+
+impl Db {
+    // 打开目录下的数据库,目录不存在则创建
+    // 已有的.data文件会被扫描以恢复索引,有效的.index文件可代替扫描
+    pub fn open<P>(path: P) -> Result<Db, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Db::open_with_options(path, Options::default())
+    }
+
+    // 使用options打开数据库
+    pub fn open_with_options<P>(path: P, options: Options) -> Result<Db, Error>
+    where
+        P: AsRef<Path>,
+    {
+        fs::create_dir_all(path.as_ref())?;
+        let cipher = options.encryption_key.as_ref().map(Cipher::new);
+        let filepool = Arc::new(Mutex::new(FilePool::new(
+            path,
+            cipher,
+            options.mmap_sealed,
+        )?));
+        let log = Arc::new(RwLock::new(Log::open(filepool, &options)?));
+        let compactor = if options.background_compaction {
+            Some(Compactor::start(log.clone(), &options)?)
+        } else {
+            None
+        };
+        Ok(Db { log, compactor })
+    }
+
+    fn reader(&self) -> RwLockReadGuard<'_, Log<'static>> {
+        self.log.read().unwrap()
+    }
+
+    fn writer(&self) -> RwLockWriteGuard<'_, Log<'static>> {
+        self.log.write().unwrap()
+    }
+
+    // 通知后台压缩线程发生了一次写入
+    fn wrote(&self) {
+        if let Some(compactor) = self.compactor.as_ref() {
+            compactor.wrote();
+        }
+    }
+
+    // 读取key对应的value
+    pub fn get<K>(&self, key: K) -> Result<Option<Vec<u8>>, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        check_key(key.as_ref())?;
+        self.reader().get_value(&key)
+    }
+
+    // 用f读取key对应的value,不复制value
+    // 开启mmap_sealed时,已封存文件中未压缩未加密的value直接借用映射内存
+    // f执行期间写入被阻塞,不应耗时过长
+    pub fn get_with<K, F, T>(&self, key: K, f: F) -> Result<Option<T>, Error>
+    where
+        K: AsRef<[u8]>,
+        F: FnOnce(&[u8]) -> T,
+    {
+        check_key(key.as_ref())?;
+        self.reader().get_with(&key, f)
+    }
+
+    // 按key顺序遍历range内的key-value,value在迭代时才读取
+    // 迭代器基于创建时刻的视图,可以用rev()逆序遍历
+    pub fn range<K, R>(&self, range: R) -> Iter
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let start = to_owned_bound(range.start_bound());
+        let end = to_owned_bound(range.end_bound());
+        self.reader().range((start, end))
+    }
+
+    // 按key顺序遍历所有以prefix开头的key-value
+    pub fn prefix<K>(&self, prefix: K) -> Iter
+    where
+        K: AsRef<[u8]>,
+    {
+        let prefix = prefix.as_ref();
+        self.reader()
+            .range((Included(prefix.to_vec()), prefix_end(prefix)))
+    }
+
+    // 按key顺序遍历所有key-value
+    pub fn iter(&self) -> Iter {
+        self.reader().range((Unbounded, Unbounded))
+    }
+
+    // 得到当前时刻的快照,之后的写入对快照不可见
+    pub fn snapshot(&self) -> Snapshot {
+        self.reader().snapshot()
+    }
+
+    // 写入key-value,已存在的key会被覆盖
+    pub fn put<K, V>(&self, key: K, value: V) -> Result<(), Error>
+    where
+        Vec<u8>: From<K>,
+        Vec<u8>: From<V>,
+    {
+        let key = Vec::from(key);
+        check_key(&key)?;
+        self.writer().set(key, value, NEVER_EXPIRE, true, false)?;
+        self.wrote();
+        Ok(())
+    }
+
+    // 写入key-value,ttl之后key过期,过期的key读取不到
+    pub fn put_with_ttl<K, V>(&self, key: K, value: V, ttl: Duration) -> Result<(), Error>
+    where
+        Vec<u8>: From<K>,
+        Vec<u8>: From<V>,
+    {
+        let key = Vec::from(key);
+        check_key(&key)?;
+        // ttl过大时截断为最大时间,不会溢出
+        let ttl = Timestamp::try_from(ttl.as_millis()).unwrap_or(Timestamp::MAX);
+        let expiry = get_timestamp()?.saturating_add(ttl);
+        self.writer().set(key, value, expiry, true, false)?;
+        self.wrote();
+        Ok(())
+    }
+
+    // 删除key,返回key是否存在
+    pub fn delete<K>(&self, key: K) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        check_key(key.as_ref())?;
+        let existed = self.writer().remove(&key, true, false)?;
+        self.wrote();
+        Ok(existed)
+    }
+
+    // 原子地提交一批写入,崩溃后要么全部可见要么全部不可见
+    pub fn write(&self, batch: WriteBatch) -> Result<(), Error> {
+        let ops: Vec<(Vec<u8>, Option<Vec<u8>>)> = batch.into_iter().collect();
+        for (key, _) in ops.iter() {
+            check_key(key)?;
+        }
+        self.writer().write_batch(ops, false)?;
+        self.wrote();
+        Ok(())
+    }
+
+    // 开始一个乐观事务,事务中的读取基于当前时刻的快照
+    pub fn begin(&self) -> Transaction {
+        Transaction::new(self.reader().snapshot())
+    }
+
+    // 提交事务,读过的key在事务开始后被改写时返回Error::Conflict
+    // 冲突或写入失败时事务的写入全部不生效,检查与写入在同一次写锁内完成
+    pub fn commit(&self, txn: Transaction) -> Result<(), Error> {
+        let (reads, writes) = txn.into_parts();
+        let mut log = self.writer();
+        check_reads(&log, &reads)?;
+        log.write_batch(writes, false)?;
+        drop(log);
+        self.wrote();
+        Ok(())
+    }
+
+    // 在事务中执行f,f返回Ok时提交事务
+    pub fn transaction<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Transaction) -> Result<T, Error>,
+    {
+        let mut txn = self.begin();
+        let result = f(&mut txn)?;
+        self.commit(txn)?;
+        Ok(result)
+    }
+
+    // 缓存命中等统计信息
+    pub fn stats(&self) -> Stats {
+        self.reader().stats()
+    }
+
+    // 立即在当前线程压缩所有待压缩的文件,压缩期间写入被阻塞
+    pub fn compact_now(&self) -> Result<(), Error> {
+        self.writer().compress()
+    }
+
+    // 同步所有写入并关闭数据库
+    // 关闭时先停止后台压缩,再写入.index文件,加快下次打开
+    pub fn close(self) -> Result<(), Error> {
+        let Db { log, compactor } = self;
+        if let Some(mut compactor) = compactor {
+            compactor.stop();
+        }
+        // 压缩线程退出后不再有其他持有者
+        let mut log = match Arc::try_unwrap(log) {
+            Ok(log) => log.into_inner().unwrap(),
+            Err(_) => unreachable!("log is still shared after the compactor stopped"),
+        };
+        log.sync_all()?;
+        log.write_all_index()
+    }
+}
+
+// 比较事务读到的版本与当前版本
+fn check_reads(log: &Log, reads: &ReadSet) -> Result<(), Error> {
+    for (key, version) in reads {
+        if log.get_version(key) != *version {
+            return Err(Error::Conflict(key.clone()));
+        }
+    }
+    Ok(())
+}
@@ -11,6 +11,14 @@ pub enum Error {
     InvalidFileId(String),
     InvalidKey(String),
     SystemTimeError(SystemTimeError),
+    // 文件头不是koundb文件或格式版本不兼容
+    InvalidFormat(String),
+    // 记录校验和不匹配,fileid为.data文件id,offset为记录在文件中的偏移
+    Corruption { fileid: u64, offset: u32 },
+    // 加密记录认证失败,密钥错误或没有提供密钥
+    Decryption { fileid: u64, offset: u32 },
+    // 事务读过的key在事务开始后被改写,事务的写入没有生效
+    Conflict(Vec<u8>),
 }
 
 impl fmt::Display for Error {
@@ -21,7 +29,23 @@ impl fmt::Display for Error {
             Error::Bucketfail(ref string) => write!(f, "Bucket fail: {}", string),
             Error::InvalidFileId(ref string) => write!(f, "Invalid FileId: {}", string),
             Error::SystemTimeError(ref err) => write!(f, "Time error: {}", err),
-            Error::InvalidKey(ref string) => write!(f,"Invaild Key: {}",string),
+            Error::InvalidKey(ref string) => write!(f, "Invaild Key: {}", string),
+            Error::InvalidFormat(ref string) => write!(f, "Invalid format: {}", string),
+            Error::Corruption { fileid, offset } => write!(
+                f,
+                "Corruption: checksum mismatch in file {} at offset {}",
+                fileid, offset
+            ),
+            Error::Decryption { fileid, offset } => write!(
+                f,
+                "Decryption failed: record in file {} at offset {} cannot be authenticated, the encryption key is wrong or missing",
+                fileid, offset
+            ),
+            Error::Conflict(ref key) => write!(
+                f,
+                "Conflict: key {:?} was modified after the transaction started",
+                String::from_utf8_lossy(key)
+            ),
         }
     }
 }
@@ -41,16 +65,20 @@ impl From<SystemTimeError> for Error {
 impl error::Error for Error {
     fn description(&self) -> &str {
         match self {
-            Error::Io(ref err) => err.description(),
-            Error::SystemTimeError(ref err) => err.description(),
+            Error::Io(..) => "IO error",
+            Error::SystemTimeError(..) => "Time error",
             Error::Allocatefail(..) => "Allocate fail",
-            Error::Bucketfail(..) => "Bucket fai",
+            Error::Bucketfail(..) => "Bucket fail",
             Error::InvalidFileId(..) => "InvalidFileId",
             Error::InvalidKey(..) => "InvalidKey",
+            Error::InvalidFormat(..) => "InvalidFormat",
+            Error::Corruption { .. } => "Corruption",
+            Error::Decryption { .. } => "Decryption",
+            Error::Conflict(..) => "Conflict",
         }
     }
 
-    fn cause(&self) -> Option<&error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             Error::Io(ref err) => Some(err),
             Error::SystemTimeError(ref err) => Some(err),
@@ -0,0 +1,574 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use util::hash_value;
+
+// 缓存的淘汰策略,在打开数据库时选择
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CachePolicy {
+    // 淘汰最久未访问的键
+    #[default]
+    Lru,
+    // 近似LRU,访问只设置引用位,不移动键
+    Clock,
+    // 按访问频率决定新键能否挤掉旧键,抵抗一次性的扫描
+    TinyLfu,
+}
+
+impl CachePolicy {
+    // 创建容量为capacity的策略
+    pub fn build<K>(self, capacity: usize) -> Box<dyn Policy<K>>
+    where
+        K: Hash + Eq + Clone + Send + 'static,
+    {
+        match self {
+            CachePolicy::Lru => Box::new(Lru::new()),
+            CachePolicy::Clock => Box::new(Clock::new()),
+            CachePolicy::TinyLfu => Box::new(TinyLfu::new(capacity)),
+        }
+    }
+}
+
+// 淘汰策略只维护键的先后与访问信息,value及其权重由Cache保存
+pub trait Policy<K>: Send {
+    // 键被加入缓存,weight为键的权重
+    fn insert(&mut self, key: K, weight: usize);
+    // 键被访问
+    fn access(&mut self, key: &K);
+    // 键被移出缓存
+    fn remove(&mut self, key: &K);
+    // 选出并移除一个要淘汰的键,可能是刚加入的键
+    fn evict(&mut self) -> Option<K>;
+}
+
+// 空链接
+const NIL: usize = usize::MAX;
+
+// 链表节点,prev与next为相邻节点在slab中的下标
+struct Node<K> {
+    key: K,
+    prev: usize,
+    next: usize,
+}
+
+// 按访问先后排列的键
+// map : key到节点下标的hashmap
+// nodes : 存放节点的slab,节点串成双向链表
+// head : 最久未访问的节点
+// tail : 最近访问的节点
+// 加入,访问与移除均为O(1)
+pub struct Lru<K> {
+    map: HashMap<K, usize>,
+    nodes: Vec<Node<K>>,
+    head: usize,
+    tail: usize,
+}
+
+impl<K> Lru<K>
+where
+    K: Hash + Eq + Clone,
+{
+    pub fn new() -> Lru<K> {
+        Lru {
+            map: HashMap::new(),
+            nodes: Vec::new(),
+            head: NIL,
+            tail: NIL,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    // 最久未访问的键
+    pub fn front(&self) -> Option<&K> {
+        self.nodes.get(self.head).map(|node| &node.key)
+    }
+    // 加入键作为最近访问的键,已存在时只移动
+    pub fn push_back(&mut self, key: K) {
+        if self.touch(&key) {
+            return;
+        }
+        let index = self.nodes.len();
+        self.map.insert(key.clone(), index);
+        self.nodes.push(Node {
+            key,
+            prev: NIL,
+            next: NIL,
+        });
+        self.link_back(index);
+    }
+    // 将键移到最近访问的位置,返回键是否存在
+    pub fn touch(&mut self, key: &K) -> bool {
+        match self.map.get(key) {
+            Some(&index) => {
+                self.unlink(index);
+                self.link_back(index);
+                true
+            }
+            None => false,
+        }
+    }
+    // 移除最久未访问的键
+    pub fn pop_front(&mut self) -> Option<K> {
+        if self.head == NIL {
+            return None;
+        }
+        let head = self.head;
+        Some(self.remove_index(head))
+    }
+    // 移除键,返回键是否存在
+    pub fn remove(&mut self, key: &K) -> bool {
+        match self.map.get(key) {
+            Some(&index) => {
+                self.remove_index(index);
+                true
+            }
+            None => false,
+        }
+    }
+    // 从链表中摘下节点
+    fn unlink(&mut self, index: usize) {
+        let (prev, next) = (self.nodes[index].prev, self.nodes[index].next);
+        if prev == NIL {
+            self.head = next;
+        } else {
+            self.nodes[prev].next = next;
+        }
+        if next == NIL {
+            self.tail = prev;
+        } else {
+            self.nodes[next].prev = prev;
+        }
+    }
+    // 将节点接到链表尾部
+    fn link_back(&mut self, index: usize) {
+        self.nodes[index].prev = self.tail;
+        self.nodes[index].next = NIL;
+        if self.tail == NIL {
+            self.head = index;
+        } else {
+            self.nodes[self.tail].next = index;
+        }
+        self.tail = index;
+    }
+    // 移除节点,slab中最后一个节点被移到空出的位置
+    fn remove_index(&mut self, index: usize) -> K {
+        self.unlink(index);
+        let node = self.nodes.swap_remove(index);
+        self.map.remove(&node.key);
+        if index < self.nodes.len() {
+            let (prev, next) = (self.nodes[index].prev, self.nodes[index].next);
+            if prev == NIL {
+                self.head = index;
+            } else {
+                self.nodes[prev].next = index;
+            }
+            if next == NIL {
+                self.tail = index;
+            } else {
+                self.nodes[next].prev = index;
+            }
+            *self.map.get_mut(&self.nodes[index].key).unwrap() = index;
+        }
+        node.key
+    }
+}
+
+impl<K> Policy<K> for Lru<K>
+where
+    K: Hash + Eq + Clone + Send,
+{
+    fn insert(&mut self, key: K, _weight: usize) {
+        self.push_back(key);
+    }
+    fn access(&mut self, key: &K) {
+        self.touch(key);
+    }
+    fn remove(&mut self, key: &K) {
+        Lru::remove(self, key);
+    }
+    fn evict(&mut self) -> Option<K> {
+        self.pop_front()
+    }
+}
+
+// CLOCK,键放在环上,访问时设置引用位
+// 指针扫过有引用位的键时清除引用位,扫到没有引用位的键时淘汰它
+pub struct Clock<K> {
+    map: HashMap<K, usize>,
+    // 环上的槽位, (键, 引用位)
+    slots: Vec<Option<(K, bool)>>,
+    // 空闲的槽位
+    free: Vec<usize>,
+    hand: usize,
+}
+
+impl<K> Clock<K>
+where
+    K: Hash + Eq + Clone,
+{
+    pub fn new() -> Clock<K> {
+        Clock {
+            map: HashMap::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+            hand: 0,
+        }
+    }
+}
+
+impl<K> Policy<K> for Clock<K>
+where
+    K: Hash + Eq + Clone + Send,
+{
+    fn insert(&mut self, key: K, _weight: usize) {
+        if self.map.contains_key(&key) {
+            self.access(&key);
+            return;
+        }
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+        };
+        self.slots[index] = Some((key.clone(), false));
+        self.map.insert(key, index);
+    }
+    fn access(&mut self, key: &K) {
+        if let Some(&index) = self.map.get(key) {
+            if let Some((_, ref mut referenced)) = self.slots[index] {
+                *referenced = true;
+            }
+        }
+    }
+    fn remove(&mut self, key: &K) {
+        if let Some(index) = self.map.remove(key) {
+            self.slots[index] = None;
+            self.free.push(index);
+        }
+    }
+    fn evict(&mut self) -> Option<K> {
+        if self.map.is_empty() {
+            return None;
+        }
+        // 最多扫过两圈
+        loop {
+            if self.hand >= self.slots.len() {
+                self.hand = 0;
+            }
+            let index = self.hand;
+            self.hand += 1;
+            match self.slots[index] {
+                Some((_, ref mut referenced)) if *referenced => *referenced = false,
+                Some(_) => {
+                    let (key, _) = self.slots[index].take().unwrap();
+                    self.map.remove(&key);
+                    self.free.push(index);
+                    return Some(key);
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+// 计数器的上限
+const MAX_COUNT: u8 = 15;
+
+// count-min sketch,近似记录键最近的访问次数
+// 计数总数达到samplesize时所有计数减半,使旧的访问逐渐失效
+struct Sketch {
+    // 4行计数器
+    table: Vec<u8>,
+    width: usize,
+    additions: usize,
+    samplesize: usize,
+}
+
+impl Sketch {
+    // 每行的宽度约为容量的4倍,减少不同键之间的冲突
+    fn new(capacity: usize) -> Sketch {
+        let width = capacity
+            .saturating_mul(4)
+            .clamp(16, 1 << 16)
+            .next_power_of_two();
+        Sketch {
+            table: vec![0; width * 4],
+            width,
+            additions: 0,
+            samplesize: width * 10,
+        }
+    }
+    // 键在每行中计数器的下标
+    fn indexes(&self, hash: u64) -> [usize; 4] {
+        let (h1, h2) = (hash as usize, (hash >> 32) as usize);
+        let mut indexes = [0; 4];
+        for (row, index) in indexes.iter_mut().enumerate() {
+            let column = h1.wrapping_add(row.wrapping_mul(h2)) & (self.width - 1);
+            *index = row * self.width + column;
+        }
+        indexes
+    }
+    fn increment(&mut self, hash: u64) {
+        for index in self.indexes(hash).iter() {
+            if self.table[*index] < MAX_COUNT {
+                self.table[*index] += 1;
+            }
+        }
+        self.additions += 1;
+        if self.additions >= self.samplesize {
+            for count in self.table.iter_mut() {
+                *count /= 2;
+            }
+            self.additions /= 2;
+        }
+    }
+    fn frequency(&self, hash: u64) -> u8 {
+        self.indexes(hash)
+            .iter()
+            .map(|index| self.table[*index])
+            .min()
+            .unwrap()
+    }
+}
+
+// 键所在的段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Segment {
+    Window,
+    Probation,
+    Protected,
+}
+
+// W-TinyLFU
+// 新键先进入占容量1%的window,window满后移入main的probation段
+// probation中再次被访问的键升入protected段,protected最多占main的80%
+// 缓存满时window中最旧的键与probation中最旧的键比较访问频率,频率低的被淘汰
+pub struct TinyLfu<K> {
+    sketch: Sketch,
+    window: Lru<K>,
+    probation: Lru<K>,
+    protected: Lru<K>,
+    // 键的权重与所在的段
+    entries: HashMap<K, (usize, Segment)>,
+    windowweight: usize,
+    protectedweight: usize,
+    totalweight: usize,
+    capacity: usize,
+    windowmax: usize,
+    protectedmax: usize,
+}
+
+impl<K> TinyLfu<K>
+where
+    K: Hash + Eq + Clone,
+{
+    pub fn new(capacity: usize) -> TinyLfu<K> {
+        let windowmax = (capacity / 100).max(1);
+        TinyLfu {
+            sketch: Sketch::new(capacity),
+            window: Lru::new(),
+            probation: Lru::new(),
+            protected: Lru::new(),
+            entries: HashMap::new(),
+            windowweight: 0,
+            protectedweight: 0,
+            totalweight: 0,
+            capacity,
+            windowmax,
+            protectedmax: capacity.saturating_sub(windowmax) * 4 / 5,
+        }
+    }
+    fn frequency(&self, key: &K) -> u8 {
+        self.sketch.frequency(hash_value(key))
+    }
+    // 将window中最旧的键移入probation
+    fn demote_window(&mut self) {
+        if let Some(key) = self.window.pop_front() {
+            let entry = self.entries.get_mut(&key).unwrap();
+            entry.1 = Segment::Probation;
+            self.windowweight -= entry.0;
+            self.probation.push_back(key);
+        }
+    }
+    // protected超出上限时,将其中最旧的键移回probation
+    fn demote_protected(&mut self) {
+        while self.protectedweight > self.protectedmax && self.protected.len() > 1 {
+            let key = self.protected.pop_front().unwrap();
+            let entry = self.entries.get_mut(&key).unwrap();
+            entry.1 = Segment::Probation;
+            self.protectedweight -= entry.0;
+            self.probation.push_back(key);
+        }
+    }
+    // 移除并返回键
+    fn take(&mut self, key: K) -> K {
+        self.detach(&key);
+        key
+    }
+    // 从所在的段中移除键
+    fn detach(&mut self, key: &K) {
+        if let Some((weight, segment)) = self.entries.remove(key) {
+            self.totalweight -= weight;
+            match segment {
+                Segment::Window => {
+                    self.window.remove(key);
+                    self.windowweight -= weight;
+                }
+                Segment::Probation => {
+                    self.probation.remove(key);
+                }
+                Segment::Protected => {
+                    self.protected.remove(key);
+                    self.protectedweight -= weight;
+                }
+            }
+        }
+    }
+}
+
+impl<K> Policy<K> for TinyLfu<K>
+where
+    K: Hash + Eq + Clone + Send,
+{
+    fn insert(&mut self, key: K, weight: usize) {
+        self.detach(&key);
+        self.sketch.increment(hash_value(&key));
+        self.entries.insert(key.clone(), (weight, Segment::Window));
+        self.window.push_back(key);
+        self.windowweight += weight;
+        self.totalweight += weight;
+        // main还有空间时,超出window的键直接移入main
+        while self.windowweight > self.windowmax && self.window.len() > 1 {
+            let headweight = self.entries[self.window.front().unwrap()].0;
+            let mainweight = self.totalweight - self.windowweight;
+            if mainweight + headweight > self.capacity.saturating_sub(self.windowmax) {
+                break;
+            }
+            self.demote_window();
+        }
+    }
+    fn access(&mut self, key: &K) {
+        self.sketch.increment(hash_value(key));
+        let (weight, segment) = match self.entries.get_mut(key) {
+            Some(entry) => {
+                let old = *entry;
+                if entry.1 == Segment::Probation {
+                    entry.1 = Segment::Protected;
+                }
+                old
+            }
+            None => return,
+        };
+        match segment {
+            Segment::Window => {
+                self.window.touch(key);
+            }
+            Segment::Probation => {
+                self.probation.remove(key);
+                self.protected.push_back(key.clone());
+                self.protectedweight += weight;
+                self.demote_protected();
+            }
+            Segment::Protected => {
+                self.protected.touch(key);
+            }
+        }
+    }
+    fn remove(&mut self, key: &K) {
+        self.detach(key);
+    }
+    fn evict(&mut self) -> Option<K> {
+        loop {
+            if self.windowweight > self.windowmax {
+                let candidate = self.window.front().cloned()?;
+                let victim = self.probation.front().or_else(|| self.protected.front());
+                match victim.cloned() {
+                    // 频率相同时保留旧键,一次性的扫描无法挤掉常用的键
+                    Some(victim) => {
+                        if self.frequency(&candidate) > self.frequency(&victim) {
+                            self.demote_window();
+                            return Some(self.take(victim));
+                        }
+                        return Some(self.take(candidate));
+                    }
+                    None => self.demote_window(),
+                }
+                continue;
+            }
+            let victim = self
+                .probation
+                .front()
+                .or_else(|| self.protected.front())
+                .or_else(|| self.window.front())
+                .cloned()?;
+            return Some(self.take(victim));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CachePolicy, Clock, Policy};
+    use cache::Cache;
+
+    fn unit(_: &u32, _: &u32) -> usize {
+        1
+    }
+
+    #[test]
+    fn clock_gives_second_chance() {
+        let mut clock = Clock::new();
+        for key in 1..=3u32 {
+            clock.insert(key, 1);
+        }
+        clock.access(&1);
+        assert_eq!(clock.evict(), Some(2));
+        clock.insert(4, 1);
+        clock.access(&3);
+        assert_eq!(clock.evict(), Some(1));
+        assert_eq!(clock.evict(), Some(4));
+        assert_eq!(clock.evict(), Some(3));
+        assert_eq!(clock.evict(), None);
+    }
+
+    // 先反复访问一组热键,再扫过大量只访问一次的键,返回扫描后仍在缓存中的热键数
+    fn hot_keys_after_scan(policy: CachePolicy) -> usize {
+        let mut cache = Cache::with_policy(100, policy, unit);
+        for _ in 0..5 {
+            for key in 0..50 {
+                if cache.get(&key).is_none() {
+                    cache.set(key, key);
+                }
+            }
+        }
+        for key in 1000..2000 {
+            cache.set(key, key);
+            assert!(cache.len() <= 100);
+        }
+        (0..50).filter(|key| cache.lookup(key).is_some()).count()
+    }
+
+    #[test]
+    fn tinylfu_resists_scans() {
+        assert_eq!(hot_keys_after_scan(CachePolicy::TinyLfu), 50);
+        assert_eq!(hot_keys_after_scan(CachePolicy::Lru), 0);
+        assert_eq!(hot_keys_after_scan(CachePolicy::Clock), 0);
+    }
+
+    #[test]
+    fn tinylfu_admits_new_frequent_keys() {
+        let mut cache = Cache::with_policy(10, CachePolicy::TinyLfu, unit);
+        for key in 0..10 {
+            cache.set(key, key);
+        }
+        // 新键被多次访问后可以挤掉只访问过一次的键
+        for _ in 0..5 {
+            cache.set(100, 100);
+            cache.get(&100);
+        }
+        assert_eq!(cache.lookup(&100), Some(&100));
+        assert_eq!(cache.len(), 10);
+    }
+}
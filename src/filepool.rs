@@ -1,108 +1,261 @@
+use compaction::FileStats;
+use crypto::Cipher;
+use data::{FileHeader, FileKind, FILE_HEADER_SIZE};
 use errors::Error;
 use freelist::FreeList;
-use std::collections::HashMap;
+use memmap2::Mmap;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
 use std::fs::{self, File, OpenOptions};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
 use std::vec::Vec;
-use util::Timestamp;
 use util::get_timestamp;
+use util::Timestamp;
 
 // 最大文件大小为32M
-pub const max_filesize: u32 = 1 << 25;
-// 最大文件句柄数为16
-const max_filehandler: usize = 16;
+pub const MAX_FILESIZE: u32 = 1 << 25;
+// 预写日志文件名
+const WAL_FILENAME: &str = "koundb.wal";
+
+// .data文件的共享句柄,以及已建立的只读映射
+type DataFile = (Arc<File>, Option<Arc<Mmap>>);
+
+// 读记录需要的文件句柄、映射与密钥,由文件池、快照与迭代器共享
+// 读者只持有读锁,互不阻塞,也不需要文件池的锁
+#[derive(Debug)]
+pub struct FileTable {
+    // .data文件的共享句柄与已建立的映射,读写均使用定位读写,不依赖句柄的读写位置
+    // 映射长度为映射时的文件长度
+    datafiles: RwLock<HashMap<Timestamp, DataFile>>,
+    // 已从表中移除、可能仍被读者持有的句柄
+    retired: Mutex<Vec<Weak<File>>>,
+    // 当前活跃文件id,活跃文件不映射
+    lastfileid: AtomicU64,
+    // 记录和.index文件的加密密钥,None时不加密
+    cipher: Option<Cipher>,
+    // 读记录时持有读锁,写.data文件时持有写锁,防止读到写了一半的记录
+    iogate: RwLock<()>,
+    // 是否映射不再追加记录的.data文件
+    mmap_sealed: bool,
+}
+
+impl FileTable {
+    pub fn cipher(&self) -> Option<&Cipher> {
+        self.cipher.as_ref()
+    }
+    pub fn iogate(&self) -> &RwLock<()> {
+        &self.iogate
+    }
+    pub fn lastfileid(&self) -> Timestamp {
+        self.lastfileid.load(Ordering::Acquire)
+    }
+    // 返回文件的共享句柄
+    pub fn get_file(&self, fileid: u64) -> Result<Arc<File>, Error> {
+        match self.datafiles.read().unwrap().get(&fileid) {
+            Some((file, _)) => Ok(file.clone()),
+            None => Err(Error::InvalidFileId("fileid not in file pool".to_string())),
+        }
+    }
+    // 返回已封存文件的只读映射,首次访问时建立,未开启映射或为活跃文件时返回None
+    pub fn get_map(&self, fileid: u64) -> Result<Option<Arc<Mmap>>, Error> {
+        if !self.mmap_sealed || fileid == self.lastfileid() {
+            return Ok(None);
+        }
+        if let Some((_, Some(map))) = self.datafiles.read().unwrap().get(&fileid) {
+            return Ok(Some(map.clone()));
+        }
+        let mut datafiles = self.datafiles.write().unwrap();
+        let (file, map) = match datafiles.get_mut(&fileid) {
+            Some(datafile) => datafile,
+            None => return Err(Error::InvalidFileId("fileid not in file pool".to_string())),
+        };
+        // 其他读者可能已经建立了映射
+        if let Some(map) = map {
+            return Ok(Some(map.clone()));
+        }
+        // .data文件从不截断,只在压缩后删除,删除前映射已从表中移除
+        // 原地写入的记录通过共享映射可见,读取时由iogate保证不会读到写了一半的记录
+        let created = Arc::new(unsafe { Mmap::map(&**file)? });
+        *map = Some(created.clone());
+        Ok(Some(created))
+    }
+    // 已建立的映射数量
+    pub fn map_count(&self) -> usize {
+        self.datafiles
+            .read()
+            .unwrap()
+            .values()
+            .filter(|(_, map)| map.is_some())
+            .count()
+    }
+    // 打开着的.data文件句柄数量,包括已删除而读者尚未释放的句柄
+    pub fn handle_count(&self) -> usize {
+        let mut retired = self.retired.lock().unwrap();
+        retired.retain(|file| file.strong_count() > 0);
+        self.datafiles.read().unwrap().len() + retired.len()
+    }
+    fn insert(&self, fileid: Timestamp, file: File) {
+        self.datafiles
+            .write()
+            .unwrap()
+            .insert(fileid, (Arc::new(file), None));
+    }
+    // 正在读取的读者仍持有句柄与映射,读完后才释放
+    fn remove(&self, fileid: Timestamp) {
+        if let Some((file, _)) = self.datafiles.write().unwrap().remove(&fileid) {
+            self.retired.lock().unwrap().push(Arc::downgrade(&file));
+        }
+    }
+    fn set_lastfileid(&self, fileid: Timestamp) {
+        self.lastfileid.store(fileid, Ordering::Release);
+    }
+}
 
+// .data文件的空间分配,以及.index与wal文件的管理
 #[derive(Debug)]
 pub struct FilePool {
-    // data文件句柄词
-    datafile_pool: HashMap<Timestamp, (FreeList, Vec<File>)>,
+    // data文件的freelist
+    datafile_pool: HashMap<Timestamp, FreeList>,
     // 目录路径
     dirpath: PathBuf,
-    // 当前活跃文件id
-    lastfileid: Timestamp,
+    // 与.data文件一致的.index文件
+    indexfiles: HashSet<Timestamp>,
+    // 与读者共享的句柄表
+    table: Arc<FileTable>,
 }
 
 impl FilePool {
-    pub fn new<'a, P>(dirpathstr: P) -> Result<FilePool, Error>
+    pub fn new<P>(dirpath: P, cipher: Option<Cipher>, mmap_sealed: bool) -> Result<FilePool, Error>
     where
-        P: Into<&'a str>,
+        P: AsRef<Path>,
     {
-        let dirpath = Path::new(dirpathstr.into());
-        let mut datafile_pool = HashMap::new();
+        let dirpath = dirpath.as_ref();
+        let mut fileids = Vec::new();
         let mut lastfileid: u64 = 0;
 
         for entry in fs::read_dir(dirpath)? {
             let entry = entry?;
             let path = entry.path();
-            if path.is_file() && path.ends_with(".data") {
-                let fileid = path.file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .parse::<u64>()
-                    .unwrap();
+            if path.is_file() && path.extension() == Some(OsStr::new("data")) {
+                let fileid = match path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok())
+                {
+                    Some(fileid) => fileid,
+                    None => continue,
+                };
                 if fileid > lastfileid {
                     lastfileid = fileid;
                 }
-                datafile_pool.insert(
-                    fileid,
-                    (
-                        FreeList::new(max_filesize),
-                        Vec::with_capacity(max_filehandler),
-                    ),
-                );
+                // 创建文件后写文件头前崩溃,文件中不可能有记录
+                if entry.metadata()?.len() < FILE_HEADER_SIZE {
+                    let file = OpenOptions::new().write(true).open(&path)?;
+                    file.set_len(0)?;
+                    FileHeader::new(FileKind::Data, fileid).write_bytes(&mut &file)?;
+                    file.sync_all()?;
+                }
+                fileids.push(fileid);
             }
         }
         let mut filepool = FilePool {
             dirpath: PathBuf::from(dirpath),
-            datafile_pool: datafile_pool,
-            lastfileid: lastfileid,
+            datafile_pool: HashMap::new(),
+            indexfiles: HashSet::new(),
+            table: Arc::new(FileTable {
+                datafiles: RwLock::new(HashMap::new()),
+                retired: Mutex::new(Vec::new()),
+                lastfileid: AtomicU64::new(lastfileid),
+                cipher,
+                iogate: RwLock::new(()),
+                mmap_sealed,
+            }),
         };
-        if filepool.datafile_pool.is_empty() {
-            let time = get_timestamp()?;
-            let file = filepool.createfile_withid(time)?;
+        for fileid in fileids {
+            let file = filepool.openfile_withid(fileid)?;
+            filepool.table.insert(fileid, file);
             filepool
                 .datafile_pool
-                .insert(time, (FreeList::new(max_filesize), vec![file]));
+                .insert(fileid, FreeList::new(MAX_FILESIZE));
+        }
+        if filepool.datafile_pool.is_empty() {
+            let fileid = filepool.create_datafile()?;
+            filepool.table.set_lastfileid(fileid);
         }
         Ok(filepool)
     }
-    // 从文件池中返回句柄
-    pub fn get_file(&mut self, fileid: u64) -> Result<File, Error> {
-        match self.datafile_pool.get_mut(&fileid) {
-            Some((_, filelist)) => Ok(filelist.pop().unwrap_or(self.openfile_withid(fileid)?)),
-            None => Err(Error::InvalidFileId("fileid not in file pool".to_string())),
-        }
+    // 与读者共享的句柄表
+    pub fn table(&self) -> Arc<FileTable> {
+        self.table.clone()
+    }
+    pub fn cipher(&self) -> Option<Cipher> {
+        self.table.cipher.clone()
+    }
+    // 返回文件的共享句柄
+    pub fn get_file(&self, fileid: u64) -> Result<Arc<File>, Error> {
+        self.table.get_file(fileid)
     }
     // 打开索引文件
     pub fn get_indexfile(&self, fileid: u64) -> Result<File, Error> {
         match self.datafile_pool.get(&fileid) {
-            Some(..) => Ok(self.getindexfile_withid(fileid)?),
+            Some(..) => Ok(self.openindexfile_withid(fileid)?),
+            None => Err(Error::InvalidFileId("fileid not in file pool".to_string())),
+        }
+    }
+    // 新建索引文件,已存在的索引文件被清空
+    pub fn create_indexfile(&self, fileid: u64) -> Result<File, Error> {
+        match self.datafile_pool.get(&fileid) {
+            Some(..) => Ok(self.createindexfile_withid(fileid)?),
             None => Err(Error::InvalidFileId("fileid not in file pool".to_string())),
         }
     }
 
-    // 从文件池中返回句柄的最大偏移
-    pub fn get_fileandfree(&mut self, fileid: u64) -> Result<(u32, File), Error> {
-        match self.datafile_pool.get_mut(&fileid) {
-            Some((freelist, filelist)) => {
-                let endoff = freelist.get_usedfilesize();
-                Ok((
-                    endoff,
-                    filelist.pop().unwrap_or(self.openfile_withid(fileid)?),
-                ))
-            }
+    // 文件池中是否存在fileid
+    pub fn has_file(&self, fileid: u64) -> bool {
+        self.datafile_pool.contains_key(&fileid)
+    }
+    // .index文件是否存在
+    pub fn has_indexfile(&self, fileid: u64) -> bool {
+        self.path_withid(fileid, false).is_file()
+    }
+    // .index文件是否与.data文件一致
+    pub fn is_indexfile_valid(&self, fileid: u64) -> bool {
+        self.indexfiles.contains(&fileid)
+    }
+    // 标记.index文件与.data文件一致
+    pub fn validate_indexfile(&mut self, fileid: u64) {
+        self.indexfiles.insert(fileid);
+    }
+    // .data文件修改前删除.index文件,崩溃后只能扫描.data文件恢复
+    pub fn invalidate_indexfile(&mut self, fileid: u64) -> Result<(), Error> {
+        if self.indexfiles.remove(&fileid) {
+            self.removefile_withid(fileid, false)?;
+            self.sync_dir()?;
+        }
+        Ok(())
+    }
+
+    // 返回文件已使用的最大偏移与共享句柄
+    pub fn get_fileandfree(&self, fileid: u64) -> Result<(u32, Arc<File>), Error> {
+        match self.datafile_pool.get(&fileid) {
+            Some(freelist) => Ok((freelist.get_usedfilesize(), self.get_file(fileid)?)),
             None => Err(Error::InvalidFileId("fileid not in file pool".to_string())),
         }
     }
 
-    // 将句柄放回文件池
-    pub fn put_file(&mut self, fileid: u64, file: File) -> Result<(), Error> {
+    // 所有data文件id,按从旧到新排序
+    pub fn get_fileids(&self) -> Vec<Timestamp> {
+        let mut fileids: Vec<Timestamp> = self.datafile_pool.keys().cloned().collect();
+        fileids.sort();
+        fileids
+    }
+    // 替换文件的freelist,用于打开时重建空闲空间
+    pub fn set_freelist(&mut self, fileid: Timestamp, freelist: FreeList) -> Result<(), Error> {
         match self.datafile_pool.get_mut(&fileid) {
-            Some((_, filelist)) => {
-                if max_filehandler > filelist.len() {
-                    filelist.push(file);
-                };
+            Some(_freelist) => {
+                *_freelist = freelist;
                 Ok(())
             }
             None => Err(Error::InvalidFileId("fileid not in file pool".to_string())),
@@ -111,17 +264,12 @@ impl FilePool {
 
     // 得到最新的活跃文件id
     pub fn get_lastfileid(&self) -> Timestamp {
-        self.lastfileid
-    }
-    // 得到最新的活跃文件
-    pub fn get_lastfile(&mut self) -> Result<File, Error> {
-        let lastfileid = self.lastfileid;
-        self.get_file(lastfileid)
+        self.table.lastfileid()
     }
     // 释放record空间
     pub fn free_room(&mut self, size: u32, offset: u32, fileid: Timestamp) -> Result<(), Error> {
         match self.datafile_pool.get_mut(&fileid) {
-            Some((freelist, _)) => Ok(freelist.free_room(offset, size)?),
+            Some(freelist) => Ok(freelist.free_room(offset, size)?),
             None => Err(Error::InvalidFileId("fileid not in file pool".to_string())),
         }
     }
@@ -129,81 +277,189 @@ impl FilePool {
     // 根据size得到目标文件的偏移
     pub fn request_room_withid(&mut self, size: u32, fileid: Timestamp) -> Result<u32, Error> {
         match self.datafile_pool.get_mut(&fileid) {
-            Some((freelist, _)) => Ok(freelist.request_room(size)?),
+            Some(freelist) => Ok(freelist.request_room(size)?),
             None => Err(Error::InvalidFileId("fileid not in file pool".to_string())),
         }
     }
     // 根据size得到最新文件的偏移,空间不够则新建文件
+    // 超过文件大小上限的记录无法写入任何文件,直接返回Error::Allocatefail
     pub fn request_room_ornew(&mut self, size: u32) -> Result<(Timestamp, u32), Error> {
-        let lastfileid = self.lastfileid;
+        if size > MAX_FILESIZE {
+            return Err(Error::Allocatefail(format!(
+                "record of {} bytes exceeds max file size {}",
+                size, MAX_FILESIZE
+            )));
+        }
+        let lastfileid = self.get_lastfileid();
         match self.request_room_withid(size, lastfileid) {
             Ok(off) => Ok((lastfileid, off)),
             Err(Error::Allocatefail(..)) => {
-                let time: Timestamp = get_timestamp()?;
-                let mut freelist = FreeList::new(max_filesize);
-                let mut filelist = Vec::with_capacity(max_filehandler);
-                self.lastfileid = time;
-                let off = freelist.request_room(size)?;
-                filelist.push(self.createfile_withid(time)?);
-                self.datafile_pool
-                    .insert(self.lastfileid, (freelist, filelist));
-                Ok((self.lastfileid, off))
+                let lastfileid = self.create_datafile()?;
+                self.table.set_lastfileid(lastfileid);
+                let off = self.request_room_withid(size, lastfileid)?;
+                Ok((lastfileid, off))
             }
             Err(err) => Err(err),
         }
     }
-    // 返回所有应当压缩的文件列表
-    pub fn compress_filelist(&self, ratio: f32) -> Result<Vec<Timestamp>, Error> {
-        let mut fileidlists = Vec::new();
-        for (fileid, (freelist, _)) in self.datafile_pool.iter() {
-            let occupyratio =
-                (freelist.get_compfilesize() as f64 / freelist.get_usedfilesize() as f64) as f32;
-            if occupyratio < ratio && *fileid != self.lastfileid {
-                fileidlists.push(*fileid);
+    // 新建空的.data文件并加入文件池,返回文件id
+    pub fn create_datafile(&mut self) -> Result<Timestamp, Error> {
+        let fileid = self.next_fileid()?;
+        let file = self.createfile_withid(fileid)?;
+        self.table.insert(fileid, file);
+        self.datafile_pool
+            .insert(fileid, FreeList::new(MAX_FILESIZE));
+        Ok(fileid)
+    }
+    // 文件池中.data文件的数量
+    pub fn datafile_count(&self) -> usize {
+        self.datafile_pool.len()
+    }
+    // 已建立的映射数量
+    pub fn map_count(&self) -> usize {
+        self.table.map_count()
+    }
+    // 所有已封存文件的空间统计,按文件id排序,key范围由调用方填写
+    pub fn file_stats(&self) -> Vec<FileStats> {
+        let lastfileid = self.get_lastfileid();
+        let mut stats: Vec<FileStats> = self
+            .datafile_pool
+            .iter()
+            .filter(|(fileid, _)| **fileid != lastfileid)
+            .map(|(fileid, freelist)| FileStats {
+                fileid: *fileid,
+                used: freelist.get_usedfilesize(),
+                dead: freelist.get_compfilesize(),
+                live_keys: 0,
+                smallest_key: None,
+                largest_key: None,
+            })
+            .collect();
+        stats.sort_by_key(|file| file.fileid);
+        stats
+    }
+
+    // 删除压缩完成的.data文件及其.index文件
+    pub fn remove_datafile(&mut self, fileid: Timestamp) -> Result<(), Error> {
+        if self.datafile_pool.remove(&fileid).is_none() {
+            return Err(Error::InvalidFileId("fileid not in file pool".to_string()));
+        }
+        self.table.remove(fileid);
+        self.indexfiles.remove(&fileid);
+        if self.has_indexfile(fileid) {
+            self.removefile_withid(fileid, false)?;
+        }
+        self.removefile_withid(fileid, true)?;
+        self.sync_dir()
+    }
+    // 已封存文件中等待压缩的空间占已用空间的比例,没有已封存文件时为0
+    pub fn dead_ratio(&self) -> f32 {
+        let (mut dead, mut used) = (0u64, 0u64);
+        let lastfileid = self.get_lastfileid();
+        for (fileid, freelist) in self.datafile_pool.iter() {
+            if *fileid != lastfileid {
+                dead += u64::from(freelist.get_compfilesize());
+                used += u64::from(freelist.get_usedfilesize());
             }
         }
-        Ok(fileidlists)
+        if used == 0 {
+            0.0
+        } else {
+            (dead as f64 / used as f64) as f32
+        }
     }
 
-    pub fn openfile_withid(&self, fileid: u64) -> Result<File, Error> {
-        let mut file_pathbuf = PathBuf::from(fileid.to_string());
-        file_pathbuf.set_extension(".data");
-        let path = self.dirpath.join(file_pathbuf.as_path());
-        Ok(OpenOptions::new().read(true).write(true).open(path)?)
+    // 新文件id为当前时间戳,且必须大于已有的文件id
+    // 压缩的目标文件可能比活跃文件新,因此与所有文件比较
+    fn next_fileid(&self) -> Result<Timestamp, Error> {
+        let time = get_timestamp()?;
+        let newest = self
+            .datafile_pool
+            .keys()
+            .cloned()
+            .fold(self.get_lastfileid(), Timestamp::max);
+        Ok(if time > newest { time } else { newest + 1 })
     }
 
-    pub fn createfile_withid(&self, fileid: u64) -> Result<File, Error> {
+    fn path_withid(&self, fileid: u64, isdata: bool) -> PathBuf {
         let mut file_pathbuf = PathBuf::from(fileid.to_string());
-        file_pathbuf.set_extension(".data");
-        let path = self.dirpath.join(file_pathbuf.as_path());
-        Ok(OpenOptions::new()
+        if isdata {
+            file_pathbuf.set_extension("data");
+        } else {
+            file_pathbuf.set_extension("index");
+        }
+        self.dirpath.join(file_pathbuf.as_path())
+    }
+
+    // 打开文件并检查文件头,句柄位于文件头之后
+    fn openfile_withkind(&self, fileid: u64, kind: FileKind) -> Result<File, Error> {
+        let path = self.path_withid(fileid, kind == FileKind::Data);
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        FileHeader::read_from(&mut file)?.check(kind, fileid)?;
+        Ok(file)
+    }
+
+    // 创建文件并写入文件头,已存在的文件被清空
+    fn createfile_withkind(&self, fileid: u64, kind: FileKind) -> Result<File, Error> {
+        let path = self.path_withid(fileid, kind == FileKind::Data);
+        let mut file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(path)?)
+            .truncate(true)
+            .open(path)?;
+        FileHeader::new(kind, fileid).write_bytes(&mut file)?;
+        file.sync_all()?;
+        Ok(file)
     }
 
-    pub fn getindexfile_withid(&self, fileid: u64) -> Result<File, Error> {
-        let mut file_pathbuf = PathBuf::from(fileid.to_string());
-        file_pathbuf.set_extension(".index");
-        let path = self.dirpath.join(file_pathbuf.as_path());
-        Ok(OpenOptions::new()
+    pub fn openfile_withid(&self, fileid: u64) -> Result<File, Error> {
+        self.openfile_withkind(fileid, FileKind::Data)
+    }
+
+    pub fn createfile_withid(&self, fileid: u64) -> Result<File, Error> {
+        let file = self.createfile_withkind(fileid, FileKind::Data)?;
+        self.sync_dir()?;
+        Ok(file)
+    }
+
+    pub fn openindexfile_withid(&self, fileid: u64) -> Result<File, Error> {
+        self.openfile_withkind(fileid, FileKind::Index)
+    }
+
+    pub fn createindexfile_withid(&self, fileid: u64) -> Result<File, Error> {
+        self.createfile_withkind(fileid, FileKind::Index)
+    }
+
+    // 打开预写日志文件,不存在时创建
+    pub fn open_walfile(&self) -> Result<File, Error> {
+        let path = self.dirpath.join(WAL_FILENAME);
+        let mut file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(path)?)
+            .truncate(false)
+            .open(path)?;
+        // 新建的文件,或写文件头前崩溃
+        if file.metadata()?.len() < FILE_HEADER_SIZE {
+            file.set_len(0)?;
+            FileHeader::new(FileKind::Wal, 0).write_bytes(&mut file)?;
+            file.sync_all()?;
+            self.sync_dir()?;
+        } else {
+            FileHeader::read_from(&mut file)?.check(FileKind::Wal, 0)?;
+        }
+        Ok(file)
     }
 
-    pub fn removefile_withid(&self,fileid:u64,isdata:bool) -> Result<(),Error> {
-        let mut file_pathbuf = PathBuf::from(fileid.to_string());
-        if isdata {
-            file_pathbuf.set_extension(".data");
-        }
-        else {
-            file_pathbuf.set_extension(".index");
-        }
-        let path = self.dirpath.join(file_pathbuf.as_path());
-        fs::remove_file(path)?;
+    pub fn removefile_withid(&self, fileid: u64, isdata: bool) -> Result<(), Error> {
+        fs::remove_file(self.path_withid(fileid, isdata))?;
+        Ok(())
+    }
+
+    // 同步目录,保证文件的创建和删除落盘
+    pub fn sync_dir(&self) -> Result<(), Error> {
+        File::open(&self.dirpath)?.sync_all()?;
         Ok(())
     }
 }
@@ -4,8 +4,6 @@ use errors::Error;
 #[derive(Debug)]
 pub struct FreeList {
     atags: Vec<Atag>,
-    // 如果freelist溢出,overflow_off为续页位置
-    overflow_off: Option<u64>,
     // freelist所能提供的最大区间
     maxfilesize: u32,
 }
@@ -19,26 +17,52 @@ impl FreeList {
         };
         FreeList {
             atags: vec![starttag],
-            overflow_off: None,
-            maxfilesize: maxfilesize,
+            maxfilesize,
         }
     }
 
+    // 根据所有存活区间(off,size)重建freelist
+    // 存活区间之间的空隙,包括已删除的记录,均为空闲空间
+    pub fn from_used(maxfilesize: u32, mut used: Vec<(u32, u32)>) -> FreeList {
+        used.sort();
+        let mut atags = Vec::new();
+        let mut off = 0;
+        for (start, size) in used {
+            if start > off {
+                atags.push(Atag {
+                    off,
+                    size: start - off,
+                });
+            }
+            off = off.max(start + size);
+        }
+        if off < maxfilesize {
+            atags.push(Atag {
+                off,
+                size: maxfilesize - off,
+            });
+        }
+        FreeList { atags, maxfilesize }
+    }
+
+    // 文件末尾的空闲区间,文件写满时不存在
+    fn tail(&self) -> Option<&Atag> {
+        match self.atags.last() {
+            Some(atag) if atag.off + atag.size == self.maxfilesize => Some(atag),
+            _ => None,
+        }
+    }
     // 所有已用空间,包括等待压缩的空间
     pub fn get_usedfilesize(&self) -> u32 {
-        self.atags[self.atags.len() - 1].off
+        match self.tail() {
+            Some(atag) => atag.off,
+            None => self.maxfilesize,
+        }
     }
     // 所有等待压缩的空间
     pub fn get_compfilesize(&self) -> u32 {
-        let mut size = 0;
-        for i in 0..self.atags.len() - 1 {
-            size += self.atags[i].size;
-        }
-        size
-    }
-    // 最大文件大小
-    pub fn get_maxfilesize(&self) -> u32 {
-        self.maxfilesize
+        let tailsize = self.tail().map_or(0, |atag| atag.size);
+        self.get_freefilesize() - tailsize
     }
     // 所有空闲空间
     pub fn get_freefilesize(&self) -> u32 {
@@ -51,7 +75,7 @@ impl FreeList {
 
     // 从freelist请求空间,返回空间的偏移
     pub fn request_room(&mut self, size: u32) -> Result<u32, Error> {
-        match self.atags.iter().position(|ref atag| atag.size >= size) {
+        match self.atags.iter().position(|atag| atag.size >= size) {
             None => Err(Error::Allocatefail("not enough free room".to_string())),
             Some(pos) => {
                 let atag = &self.atags[pos].clone();
@@ -59,8 +83,7 @@ impl FreeList {
                 if atag.size == size {
                     self.atags.remove(pos);
                 } else {
-                    let mut _atag = self.atags.get_mut(pos).unwrap(); 
-                    *_atag =  atag.reduce(atag.size)?;
+                    self.atags[pos] = atag.reduce(size)?;
                 }
                 Ok(off)
             }
@@ -75,10 +98,7 @@ impl FreeList {
                 "can not free same room again".to_string(),
             )),
             Err(pos) => {
-                let atag = Atag {
-                    off: off,
-                    size: size,
-                };
+                let atag = Atag { off, size };
                 // 尝试合并插入位置左右的atag
                 // 插入位置为0时,左侧atag不存在
                 // 否则左侧atag的index为pos-1
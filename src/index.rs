@@ -1,87 +1,519 @@
-use data::{Record, RecordWriter,Recordfile};
+use cache::ShardedCache;
+use compaction::{CompactionFilter, CompactionPolicy, FileStats, FilterDecision};
+use compress::Compression;
+use data::{
+    is_expired, Index, Indexfile, Record, RecordKind, RecordWriter, Recordfile, FILE_HEADER_SIZE,
+    NEVER_EXPIRE,
+};
 use errors::Error;
-use filepool::{FilePool,max_filesize};
-use std::collections::{BTreeMap, HashSet,VecDeque};
-use std::io::{Seek, SeekFrom};
-use std::sync::{Arc, Mutex};
+use filepool::{FilePool, FileTable, MAX_FILESIZE};
+use freelist::FreeList;
+use options::Options;
+use snapshot::Snapshot;
+use stats::Stats;
+use std::collections::{hash_map, BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::ops::Bound::{self, Excluded, Included};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
 use std::vec::Vec;
-use util::{get_timestamp, Timestamp,roundup};
+use util::{get_timestamp, Timestamp};
 
-// FIXME 应该可以被配置
-const ratio:f32 = 0.75;
+// key -> (记录的时间戳, value)
+type ValueCache = ShardedCache<Vec<u8>, (Timestamp, Vec<u8>)>;
+// 批量写入中已分配空间但尚未写入的记录及其位置
+type Staged<'a> = Option<(Slot, Record<'a>)>;
+// 批量写入中被删除的记录位置,原位置待写入的删除记录,以及快照释放后清除value的删除记录
+type Retired<'a> = Option<(Slot, Record<'a>, Option<Record<'a>>)>;
 
+// 缓存项的权重为key与value的字节数
+#[allow(clippy::ptr_arg)]
+fn cache_weight(key: &Vec<u8>, entry: &(Timestamp, Vec<u8>)) -> usize {
+    key.len() + entry.1.len()
+}
 
 #[derive(Debug)]
-struct Log<'a> {
+pub struct Log<'a> {
     // datafile的句柄池
     filepool: Arc<Mutex<FilePool>>,
-    // 待同步文件列表
-    syncpool: HashSet<Timestamp>,
-    // key-offset索引
-    indexmap: BTreeMap<Vec<u8>, Slot>,
+    // 读记录使用的句柄表,读取时不需要filepool的锁
+    files: Arc<FileTable>,
+    // key-offset索引,与快照共享,修改时写时复制
+    indexmap: Arc<BTreeMap<Vec<u8>, Slot>>,
     // 代写的indexfile列表
     writer: RecordWriter<'a>,
+    // 最近一次写入的时间戳
+    lasttime: Timestamp,
+    // 新记录value的压缩算法
+    compression: Compression,
+    // 新记录是否加密
+    encrypted: bool,
+    // 已创建的快照视图,快照释放后视图失效
+    // 读者可以并发地创建快照,因此单独加锁
+    views: Mutex<Vec<Weak<View>>>,
+    // 仍可能被快照引用而延迟释放的空间
+    // (释放时的快照序号, slot, 释放前写入原位置的删除记录)
+    deferred: Vec<(u64, Slot, Option<Record<'a>>)>,
+    // 下一个快照的序号
+    nextseq: AtomicU64,
+    // value缓存,时间戳与索引不一致的缓存项无效
+    cache: Option<ValueCache>,
+    // 缓存命中与未命中的读取次数
+    cachehits: AtomicU64,
+    cachemisses: AtomicU64,
+    // 完成的压缩轮数
+    compactions: AtomicU64,
+    // 打开时扫描到损坏记录是否继续打开
+    repair: bool,
+    // 打开时扫描到的损坏记录占用的(偏移,大小),空间保留不用
+    // 这些文件不写.index文件,每次打开都重新扫描
+    corrupt: HashMap<Timestamp, Vec<(u32, u32)>>,
+    // 存活记录已损坏而放弃压缩的文件,之后的压缩不再选择它们
+    badfiles: HashSet<Timestamp>,
+    // 上一轮压缩的目标文件,下一轮先填满它的剩余空间
+    compaction_target: Option<Timestamp>,
+    // 选择待压缩文件的策略
+    policy: Arc<dyn CompactionPolicy>,
+    // 压缩时过滤存活记录,None时全部保留
+    filter: Option<Arc<dyn CompactionFilter>>,
 }
 
 impl<'a> Log<'a> {
-    fn new(datafilepool: Arc<Mutex<FilePool>>) -> Log<'a>
-where {
-        Log {
+    // 打开日志,重放wal后扫描所有.data文件重建索引和freelist
+    pub fn open(datafilepool: Arc<Mutex<FilePool>>, options: &Options) -> Result<Log<'a>, Error> {
+        let files = datafilepool.lock().unwrap().table();
+        let mut log = Log {
             filepool: datafilepool.clone(),
-            syncpool: HashSet::new(),
-            indexmap: BTreeMap::new(),
-            writer: RecordWriter::new(datafilepool.clone()),
+            files,
+            indexmap: Arc::new(BTreeMap::new()),
+            writer: RecordWriter::open(datafilepool.clone())?,
+            lasttime: 0,
+            compression: options.compression,
+            encrypted: options.encryption_key.is_some(),
+            views: Mutex::new(Vec::new()),
+            deferred: Vec::new(),
+            nextseq: AtomicU64::new(0),
+            cache: match options.cache_capacity {
+                0 => None,
+                capacity => Some(ShardedCache::new(
+                    capacity,
+                    options.cache_max_entry,
+                    options.cache_policy,
+                    cache_weight,
+                )),
+            },
+            cachehits: AtomicU64::new(0),
+            cachemisses: AtomicU64::new(0),
+            compactions: AtomicU64::new(0),
+            repair: options.repair,
+            corrupt: HashMap::new(),
+            badfiles: HashSet::new(),
+            compaction_target: None,
+            policy: options.compaction_policy.clone(),
+            filter: options.compaction_filter.clone(),
+        };
+        log.writer.replay()?;
+        log.recover()?;
+        Ok(log)
+    }
+    // 同一key存在多条记录时,time最新的记录有效,time相同时Put优先于Delete
+    // 最新记录为Delete时key已被删除,失效的Put记录被改写为Delete记录
+    // 保留了value的Delete记录被改写为只有key的删除记录
+    // time相同的两条Put是压缩复制后、删除原文件前崩溃留下的副本,保留一条,另一条被清零
+    fn recover(&mut self) -> Result<(), Error> {
+        let fileids = self.filepool.lock().unwrap().get_fileids();
+        let mut newestmap: HashMap<Vec<u8>, (Slot, RecordKind)> = HashMap::new();
+        let mut stalelist = Vec::new();
+        let mut copies = Vec::new();
+        for fileid in fileids.iter().cloned() {
+            for index in self.load_indexes(fileid)? {
+                let slot = Slot::new(index.offset, fileid, index.size, index.time, index.expiry);
+                self.lasttime = self.lasttime.max(index.time);
+                if index.kind == RecordKind::Delete {
+                    let tombstone =
+                        Record::tombstone(index.key.clone(), index.time, self.encrypted);
+                    if tombstone.allocsize() < slot.size {
+                        stalelist.push((slot.clone(), tombstone));
+                    }
+                }
+                let record = (slot, index.kind);
+                let (stale, newest, key) = match newestmap.entry(index.key) {
+                    hash_map::Entry::Vacant(entry) => {
+                        entry.insert(record);
+                        continue;
+                    }
+                    hash_map::Entry::Occupied(mut entry) => {
+                        let stale = if supersedes(&record, entry.get()) {
+                            entry.insert(record)
+                        } else {
+                            record
+                        };
+                        (stale, entry.get().clone(), entry.key().clone())
+                    }
+                };
+                match stale {
+                    (stale, RecordKind::Put)
+                        if newest.1 == RecordKind::Put && newest.0.time == stale.time =>
+                    {
+                        copies.push(stale)
+                    }
+                    (stale, RecordKind::Put) => {
+                        let tombstone = Record::tombstone(key, stale.time, self.encrypted);
+                        stalelist.push((stale, tombstone));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for copy in copies {
+            self.writer.erase(copy.fileid, copy.offset, copy.size);
+        }
+        // 已过期的key不再加入索引,其空间可以被复用
+        let now = get_timestamp()?;
+        for (key, (slot, kind)) in newestmap {
+            if kind == RecordKind::Put && !slot.is_expired(now) {
+                Arc::make_mut(&mut self.indexmap).insert(key, slot);
+            }
+        }
+        // 改写失效的Put记录,防止其在之后的恢复中复活
+        for (stale, tombstone) in stalelist {
+            self.write_tombstone(&stale, tombstone)?;
+        }
+        self.writer.write_all(true)?;
+        // 存活记录以外的空间均为空闲空间
+        let mut usedmap: HashMap<Timestamp, Vec<(u32, u32)>> = HashMap::new();
+        for slot in self.indexmap.values() {
+            usedmap
+                .entry(slot.fileid)
+                .or_default()
+                .push((slot.offset, slot.size));
+        }
+        for (fileid, spans) in self.corrupt.iter() {
+            usedmap
+                .entry(*fileid)
+                .or_default()
+                .extend(spans.iter().cloned());
         }
+        let mut filepool = self.filepool.lock().unwrap();
+        for fileid in fileids {
+            let used = usedmap.remove(&fileid).unwrap_or_default();
+            filepool.set_freelist(fileid, FreeList::from_used(MAX_FILESIZE, used))?;
+        }
+        Ok(())
+    }
+    // 读取文件中所有存活记录的位置
+    // 优先使用.index文件,不存在、已过期或已损坏时扫描.data文件
+    // 扫描成功后才删除过期或损坏的.index文件,密钥错误时扫描失败,.index文件保持不变
+    // 扫描到损坏记录时返回Error::Corruption,设置了repair时保留其空间并继续
+    fn load_indexes(&mut self, fileid: Timestamp) -> Result<Vec<Index>, Error> {
+        let mut filepool = self.filepool.lock().unwrap();
+        let cipher = filepool.cipher();
+        let file = filepool.get_file(fileid)?;
+        let datalen = file.metadata()?.len();
+        let hinted = filepool.has_indexfile(fileid);
+        if hinted {
+            let hint = filepool.get_indexfile(fileid).and_then(|mut indexfile| {
+                Indexfile::read_from(&mut BufReader::new(&mut indexfile), fileid, cipher.as_ref())
+            });
+            match hint {
+                Ok(hint) if hint.datalen == datalen => {
+                    filepool.validate_indexfile(fileid);
+                    return Ok(hint.indexes);
+                }
+                // 无法读取.index文件不说明其内容有误
+                Err(Error::Io(err)) if err.kind() != io::ErrorKind::UnexpectedEof => {
+                    return Err(Error::Io(err))
+                }
+                _ => {}
+            }
+        }
+        let endoff = (datalen - FILE_HEADER_SIZE) as u32;
+        let recordfile = Recordfile::read_from(&file, fileid, endoff, cipher.as_ref())?;
+        if let Some(&(offset, _)) = recordfile.corrupt.first() {
+            if !self.repair {
+                return Err(Error::Corruption { fileid, offset });
+            }
+            self.corrupt.insert(fileid, recordfile.corrupt.clone());
+        }
+        if hinted {
+            filepool.removefile_withid(fileid, false)?;
+        }
+        Ok(recordfile
+            .records
+            .iter()
+            .map(|(offset, record)| Index::new(record, *offset))
+            .collect())
     }
-    // 得到record
-    fn get_record<K>(&mut self, key: &K) -> Result<Option<Record<'a>>, Error>
+    // 为所有修改过的.data文件重写.index文件,下次打开时无需扫描
+    pub fn write_all_index(&mut self) -> Result<(), Error> {
+        let mut indexmap: HashMap<Timestamp, Vec<Index>> = HashMap::new();
+        for (key, slot) in self.indexmap.iter() {
+            indexmap.entry(slot.fileid).or_default().push(Index {
+                kind: RecordKind::Put,
+                key: key.clone(),
+                offset: slot.offset,
+                size: slot.size,
+                time: slot.time,
+                expiry: slot.expiry,
+            });
+        }
+        let mut filepool = self.filepool.lock().unwrap();
+        let cipher = filepool.cipher();
+        for fileid in filepool.get_fileids() {
+            if filepool.is_indexfile_valid(fileid) || self.corrupt.contains_key(&fileid) {
+                continue;
+            }
+            let datalen = filepool.get_file(fileid)?.metadata()?.len();
+            let indexfile = Indexfile {
+                fileid,
+                datalen,
+                indexes: indexmap.remove(&fileid).unwrap_or_default(),
+            };
+            let mut file = filepool.create_indexfile(fileid)?;
+            let mut writer = BufWriter::new(&mut file);
+            indexfile.write_bytes(&mut writer, cipher.as_ref())?;
+            writer.flush()?;
+            drop(writer);
+            file.sync_all()?;
+            filepool.validate_indexfile(fileid);
+        }
+        filepool.sync_dir()
+    }
+    // 单调递增的时间戳,保证同一key的新记录time更大
+    fn next_time(&mut self) -> Result<Timestamp, Error> {
+        let time = get_timestamp()?.max(self.lasttime + 1);
+        self.lasttime = time;
+        Ok(time)
+    }
+    // key当前记录的时间戳,key不存在时返回None
+    pub fn get_version(&self, key: &[u8]) -> Option<Timestamp> {
+        self.indexmap.get(key).map(|slot| slot.time)
+    }
+    // 按key顺序遍历range内的记录,遍历基于创建时刻的视图
+    pub fn range(&self, range: (Bound<Vec<u8>>, Bound<Vec<u8>>)) -> Iter {
+        Iter::new(self.view(), self.files.clone(), range)
+    }
+    // 创建当前索引的快照,快照存活期间其引用的空间不会被复用
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.view(), self.files.clone())
+    }
+    // 冻结当前索引,视图存活期间其引用的空间不会被复用
+    fn view(&self) -> Arc<View> {
+        let view = Arc::new(View {
+            seq: self.nextseq.fetch_add(1, Ordering::SeqCst),
+            indexmap: self.indexmap.clone(),
+        });
+        let mut views = self.views.lock().unwrap();
+        views.retain(|view| view.strong_count() > 0);
+        views.push(Arc::downgrade(&view));
+        view
+    }
+    // 释放slot的空间,存在快照时延迟到这些快照都释放之后
+    // scrub为快照释放后写入原位置的删除记录,用于清除快照仍在读取的value
+    fn free_slot(&mut self, slot: Slot, scrub: Option<Record<'a>>) -> Result<(), Error> {
+        let views = self.views.get_mut().unwrap();
+        views.retain(|view| view.strong_count() > 0);
+        if views.is_empty() && scrub.is_none() {
+            self.filepool
+                .lock()
+                .unwrap()
+                .free_room(slot.size, slot.offset, slot.fileid)
+        } else {
+            self.deferred.push((*self.nextseq.get_mut(), slot, scrub));
+            Ok(())
+        }
+    }
+    // 归还不再被任何快照引用的延迟释放空间
+    // 序号为seq的释放只可能被序号小于seq的快照引用
+    fn release_deferred(&mut self) -> Result<(), Error> {
+        if self.deferred.is_empty() {
+            return Ok(());
+        }
+        let views = self.views.get_mut().unwrap();
+        views.retain(|view| view.strong_count() > 0);
+        let oldest = views
+            .iter()
+            .filter_map(|view| view.upgrade())
+            .map(|view| view.seq)
+            .min()
+            .unwrap_or(u64::MAX);
+        let (ready, pending): (Vec<_>, Vec<_>) = self
+            .deferred
+            .drain(..)
+            .partition(|(seq, _, _)| *seq <= oldest);
+        self.deferred = pending;
+        // 先清除value再归还空间,写入失败时留待下次重试
+        let mut scrubbed = false;
+        for (_, slot, scrub) in ready.iter() {
+            if let Some(tombstone) = scrub {
+                self.write_tombstone(slot, tombstone.clone())?;
+                scrubbed = true;
+            }
+        }
+        if scrubbed {
+            if let Err(err) = self.writer.write_all(false) {
+                self.deferred.extend(ready);
+                return Err(err);
+            }
+        }
+        let mut filepool = self.filepool.lock().unwrap();
+        for (_, slot, _) in ready {
+            filepool.free_room(slot.size, slot.offset, slot.fileid)?;
+        }
+        Ok(())
+    }
+    // slot是否仍被存活快照中的key引用
+    fn is_viewed(&mut self, key: &[u8], slot: &Slot) -> bool {
+        let views = self.views.get_mut().unwrap();
+        views.retain(|view| view.strong_count() > 0);
+        views
+            .iter()
+            .filter_map(|view| view.upgrade())
+            .any(|view| match view.indexmap.get(key) {
+                Some(viewed) => viewed.fileid == slot.fileid && viewed.offset == slot.offset,
+                None => false,
+            })
+    }
+    // 被存活快照引用的文件
+    fn pinned_files(&mut self) -> HashSet<Timestamp> {
+        let views = self.views.get_mut().unwrap();
+        views.retain(|view| view.strong_count() > 0);
+        let mut fileids = HashSet::new();
+        for view in views.iter().filter_map(|view| view.upgrade()) {
+            fileids.extend(view.indexmap.values().map(|slot| slot.fileid));
+        }
+        fileids
+    }
+    // 得到value,优先从缓存读取,未命中时读取.data文件并填入缓存
+    // 从映射读取的未压缩value不填入缓存,映射本身已省去读取文件,与get_with一致
+    pub fn get_value<K>(&self, key: &K) -> Result<Option<Vec<u8>>, Error>
     where
         K: AsRef<[u8]>,
     {
-        let slot = self.indexmap.get(key.as_ref());
-        if let Some(_slot) = slot {
-            let mut file = self.filepool.lock().unwrap().get_file(_slot.fileid)?;
-            file.seek(SeekFrom::Start(_slot.offset as u64));
-            let record = Record::read_from(&mut file)?;
-            if record.is_none() {
-                Err(Error::InvalidKey("key in map but not in disk".to_string()))
-            } else {
-                Ok(record)
+        let key = key.as_ref();
+        let slot = match self.indexmap.get(key) {
+            Some(slot) if !slot.is_expired(get_timestamp()?) => slot.clone(),
+            _ => return Ok(None),
+        };
+        if let Some(cache) = self.cache.as_ref() {
+            if let Some((time, value)) = cache.get(key) {
+                if time == slot.time {
+                    self.cachehits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(Some(value));
+                }
             }
-        } else {
-            Ok(None)
         }
+        self.cachemisses.fetch_add(1, Ordering::Relaxed);
+        let (value, mapped) = with_slot(&self.files, &slot, |record| {
+            let mapped = record.is_mapped();
+            Ok((record.into_value()?, mapped))
+        })?;
+        if !mapped {
+            self.fill_cache(key.to_vec(), slot.time, &value);
+        }
+        Ok(Some(value))
     }
-    // 设置key,存在则先删除再追加
-    fn get_value<K>(&mut self, key: &K) -> Result<Option<Vec<u8>>, Error>
+    // 用f读取value,未压缩的value直接借用缓存或映射的.data文件
+    // 与get_value相同,从映射借用的value不填入缓存,f执行期间持有读记录的锁,不应耗时过长
+    pub fn get_with<K, F, T>(&self, key: &K, f: F) -> Result<Option<T>, Error>
     where
         K: AsRef<[u8]>,
+        F: FnOnce(&[u8]) -> T,
     {
-        match self.get_record(key)? {
-            None => Ok(None),
-            Some(record) => Ok(Some(Vec::from(record.value))),
+        let key = key.as_ref();
+        let slot = match self.indexmap.get(key) {
+            Some(slot) if !slot.is_expired(get_timestamp()?) => slot.clone(),
+            _ => return Ok(None),
+        };
+        if let Some(cache) = self.cache.as_ref() {
+            if let Some((time, value)) = cache.get(key) {
+                if time == slot.time {
+                    self.cachehits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(Some(f(&value)));
+                }
+            }
+        }
+        self.cachemisses.fetch_add(1, Ordering::Relaxed);
+        with_slot(&self.files, &slot, |record| {
+            let value = record.value()?;
+            if !record.is_mapped() {
+                self.fill_cache(key.to_vec(), slot.time, &value);
+            }
+            Ok(Some(f(&value)))
+        })
+    }
+    // 缓存key在time时刻写入的value
+    fn fill_cache(&self, key: Vec<u8>, time: Timestamp, value: &[u8]) {
+        if let Some(cache) = self.cache.as_ref() {
+            cache.set(key, (time, value.to_vec()));
+        }
+    }
+    // 移除key的缓存项
+    fn invalidate(&self, key: &[u8]) {
+        if let Some(cache) = self.cache.as_ref() {
+            cache.remove(key);
+        }
+    }
+    // 当前的统计信息
+    pub fn stats(&self) -> Stats {
+        let filepool = self.filepool.lock().unwrap();
+        Stats {
+            cache_hits: self.cachehits.load(Ordering::Relaxed),
+            cache_misses: self.cachemisses.load(Ordering::Relaxed),
+            compactions: self.compactions.load(Ordering::Relaxed),
+            data_files: filepool.datafile_count() as u64,
+            mapped_files: filepool.map_count() as u64,
+            corrupt_records: self.corrupt.values().map(Vec::len).sum::<usize>() as u64,
+            bad_files: self.badfiles.len() as u64,
+            // .data文件的句柄,加上wal的句柄
+            file_handles: self.files.handle_count() as u64 + 1,
         }
     }
-    fn set<K, V>(&mut self, key: K, value: V, write_now: bool, sync_now: bool) -> Result<(), Error>
+    // 设置key,存在则先删除再追加
+    pub fn set<K, V>(
+        &mut self,
+        key: K,
+        value: V,
+        expiry: Timestamp,
+        write_now: bool,
+        sync_now: bool,
+    ) -> Result<(), Error>
     where
         Vec<u8>: From<K>,
         Vec<u8>: From<V>,
     {
         let keyvec = Vec::from(key);
-        match self.indexmap.get(&keyvec) {
-            None => {}
-            Some(_) => {
-                self.remove(&keyvec, write_now, sync_now)?;
+        let old = match self.indexmap.get(&keyvec).cloned() {
+            None => return self.append(keyvec, value, expiry, write_now, sync_now),
+            Some(old) => old,
+        };
+        self.release_deferred()?;
+        // 删除记录的time早于新记录,恢复时新记录有效
+        let deltime = self.next_time()?;
+        // 先为新记录分配空间,value过大或分配失败时旧记录不受影响
+        let (record, newslot) = self.prepare_record(keyvec.clone(), Vec::from(value), expiry)?;
+        // 删除记录与新记录在wal中作为同一批次提交,崩溃后要么都生效要么都不生效
+        let scrub = match self.insert_tombstone(&keyvec, &old, deltime) {
+            Ok(scrub) => scrub,
+            Err(err) => {
+                self.discard(&keyvec, &newslot)?;
+                return Err(err);
             }
-        }
-        self.append(keyvec, value, write_now, sync_now)
+        };
+        self.writer
+            .insert_record(newslot.fileid, newslot.offset, record)?;
+        self.write_record(&keyvec, &newslot, write_now, sync_now)?;
+        self.free_slot(old, scrub)?;
+        Arc::make_mut(&mut self.indexmap).insert(keyvec, newslot);
+        Ok(())
     }
 
-    // 追加record
-    fn append<K, V>(
+    // 追加record,expiry为NEVER_EXPIRE时永不过期
+    pub fn append<K, V>(
         &mut self,
         key: K,
         value: V,
+        expiry: Timestamp,
         write_now: bool,
         sync_now: bool,
     ) -> Result<(), Error>
@@ -91,117 +523,678 @@ where {
     {
         let keyvec = Vec::from(key);
         let valvec = Vec::from(value);
-        let time = get_timestamp()?;
-        let record = Record::new(keyvec.clone(), valvec, time);
-        // 获取追加位置,调整lastfileid及其freelist
-        let (fileid, offset) = self.writer.get_offset(&record)?;
-        let newslot = Slot::new(offset, fileid, time);
+        self.release_deferred()?;
+        let (record, newslot) = self.prepare_record(keyvec.clone(), valvec, expiry)?;
         // 插入record
-        self.writer.insert_record(fileid, offset, record)?;
+        self.writer
+            .insert_record(newslot.fileid, newslot.offset, record)?;
         // 写记录
-        if write_now {
-            self.writer.write_all(sync_now)?;
-        }
+        self.write_record(&keyvec, &newslot, write_now, sync_now)?;
         // 加入内存中的btree
-        self.indexmap.insert(keyvec, newslot);
-        // 加入同步池
-        self.syncpool.insert(fileid);
+        Arc::make_mut(&mut self.indexmap).insert(keyvec, newslot);
         Ok(())
     }
-    // 删除record
-    fn remove<K>(
+    // 按当前选项压缩和加密新value,并分配追加位置,调整lastfileid及其freelist
+    // 新记录的time更大,同时填入缓存
+    fn prepare_record(
         &mut self,
-        key: &K,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expiry: Timestamp,
+    ) -> Result<(Record<'a>, Slot), Error> {
+        let time = self.next_time()?;
+        self.fill_cache(key.clone(), time, &value);
+        let mut record = Record::new(key, value, time).compress(self.compression);
+        record.expiry = expiry;
+        record.encrypted = self.encrypted;
+        let (fileid, offset) = self.writer.get_offset(&record)?;
+        let slot = Slot::new(offset, fileid, record.allocsize(), time, expiry);
+        Ok((record, slot))
+    }
+    // 提交并写入key在slot处的新记录,write_now为false时留待之后的写入一起提交
+    // 提交失败时归还新记录的空间,已进入wal的写入会在重新打开时重放,之后的失败不归还
+    fn write_record(
+        &mut self,
+        key: &[u8],
+        slot: &Slot,
         write_now: bool,
         sync_now: bool,
-    ) -> Result<Option<Record<'a>>, Error>
+    ) -> Result<(), Error> {
+        if !write_now {
+            return Ok(());
+        }
+        match self.writer.commit() {
+            Ok(entries) => self.writer.apply_committed(&entries, sync_now),
+            Err(err) => {
+                self.discard(key, slot)?;
+                Err(err)
+            }
+        }
+    }
+    // 归还尚未提交的新记录的空间并移除key的缓存项
+    fn discard(&mut self, key: &[u8], slot: &Slot) -> Result<(), Error> {
+        self.invalidate(key);
+        self.filepool
+            .lock()
+            .unwrap()
+            .free_room(slot.size, slot.offset, slot.fileid)
+    }
+    // 准备key在slot原位置的time时刻的删除记录,返回删除记录与快照释放后的删除记录
+    // 原记录不被读取,损坏的记录同样可以被删除或覆盖
+    // 快照仍引用slot时保留原value,快照释放后再改写为只有key的删除记录
+    fn prepare_tombstone(
+        &mut self,
+        key: &[u8],
+        slot: &Slot,
+        time: Timestamp,
+    ) -> Result<(Record<'a>, Option<Record<'a>>), Error> {
+        let tombstone = Record::tombstone(key.to_vec(), time, self.encrypted);
+        if !self.is_viewed(key, slot) {
+            return Ok((tombstone, None));
+        }
+        match read_slot(&self.files, slot) {
+            Ok(record) => Ok((record.tombstone_with_value(time), Some(tombstone))),
+            Err(Error::Io(err)) => Err(Error::Io(err)),
+            // 快照同样无法读取原记录,不必保留
+            Err(_) => Ok((tombstone, None)),
+        }
+    }
+    // 在slot原位置插入删除记录,返回快照释放后写入的删除记录
+    fn insert_tombstone(
+        &mut self,
+        key: &[u8],
+        slot: &Slot,
+        time: Timestamp,
+    ) -> Result<Option<Record<'a>>, Error> {
+        let (tombstone, scrub) = self.prepare_tombstone(key, slot, time)?;
+        self.write_tombstone(slot, tombstone)?;
+        Ok(scrub)
+    }
+    // 在slot原位置写入删除记录,并清零原记录其余的空间
+    // 删除记录比原记录大时(原记录未加密而现在加密)只清零原记录
+    fn write_tombstone(&mut self, slot: &Slot, tombstone: Record<'a>) -> Result<(), Error> {
+        let size = tombstone.size() as u32;
+        if tombstone.allocsize() > slot.size {
+            self.writer.erase(slot.fileid, slot.offset, slot.size);
+            return Ok(());
+        }
+        self.writer
+            .insert_record(slot.fileid, slot.offset, tombstone)?;
+        if size < slot.size {
+            self.writer
+                .erase(slot.fileid, slot.offset + size, slot.size - size);
+        }
+        Ok(())
+    }
+    // 删除key,返回key是否存在,已过期的key同样被删除,但返回false
+    pub fn remove<K>(&mut self, key: &K, write_now: bool, sync_now: bool) -> Result<bool, Error>
     where
         K: AsRef<[u8]>,
     {
-        match self.get_record(key)? {
-            None => Ok(None),
-            Some(record) => {
+        let now = get_timestamp()?;
+        match self.indexmap.get(key.as_ref()).cloned() {
+            None => Ok(false),
+            Some(slot) => {
                 // 使用原slot位置为写位置
-                let slot = self.indexmap.get(key.as_ref()).unwrap().clone();
-                let keyvec = vec![0; record.key.len()];
-                let valvec = vec![0; record.value.len()];
-                let delrecord = Record::new(keyvec, valvec, 0);
-                // 插入空record
-                self.writer
-                    .insert_record(slot.fileid, slot.offset, delrecord)?;
+                let time = self.next_time()?;
+                let scrub = self.insert_tombstone(key.as_ref(), &slot, time)?;
                 // 写记录
                 if write_now {
                     self.writer.write_all(sync_now)?;
                 }
+                let expired = slot.is_expired(now);
                 // 释放recod空间
-                self.writer.free_record(&record, slot.fileid, slot.offset);
+                self.free_slot(slot, scrub)?;
                 // 删除内存中的btree
-                self.indexmap.remove(key.as_ref());
-                // 加入同步池
-                self.syncpool.insert(slot.fileid);
-                Ok(Some(record))
+                Arc::make_mut(&mut self.indexmap).remove(key.as_ref());
+                self.invalidate(key.as_ref());
+                Ok(!expired)
             }
         }
     }
 
-    // 同步
-    fn sync_all(&mut self) -> Result<(), Error> {
-        for fileid in self.syncpool.drain() {
-            let file = self.filepool.lock().unwrap().get_file(fileid)?;
-            file.sync_all()?;
+    // 原子地写入一批修改,全部记录作为wal中的一个批次提交
+    // 每个key只能出现一次,否则后一次修改会读到尚未写入的记录
+    // 先为所有写入分配空间并读出被删除的记录,任何一步失败时归还已分配的空间,
+    // 索引不受影响,整批写入都不生效
+    pub fn write_batch<I>(&mut self, ops: I, sync_now: bool) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Option<Vec<u8>>)>,
+    {
+        self.release_deferred()?;
+        let mut staged = Vec::new();
+        for (key, value) in ops {
+            match self.stage(&key, value) {
+                Ok((old, new)) => staged.push((key, old, new)),
+                Err(err) => {
+                    self.invalidate(&key);
+                    self.unstage(staged)?;
+                    return Err(err);
+                }
+            }
+        }
+        let mut applied = Vec::with_capacity(staged.len());
+        for (key, old, new) in staged {
+            let old = match old {
+                Some((slot, tombstone, scrub)) => {
+                    self.write_tombstone(&slot, tombstone)?;
+                    Some((slot, scrub))
+                }
+                None => None,
+            };
+            let new = match new {
+                Some((slot, record)) => {
+                    self.writer
+                        .insert_record(slot.fileid, slot.offset, record)?;
+                    Some(slot)
+                }
+                None => None,
+            };
+            applied.push((key, old, new));
+        }
+        // 提交失败时整批写入不会被重放,归还新记录的空间
+        let entries = match self.writer.commit() {
+            Ok(entries) => entries,
+            Err(err) => {
+                for (key, _, new) in applied.iter() {
+                    match new {
+                        Some(slot) => self.discard(key, slot)?,
+                        None => self.invalidate(key),
+                    }
+                }
+                return Err(err);
+            }
+        };
+        // 写入失败时记录已进入wal,重新打开时会被重放,因此不归还新记录的空间
+        if let Err(err) = self.writer.apply_committed(&entries, sync_now) {
+            for (key, _, _) in applied.iter() {
+                self.invalidate(key);
+            }
+            return Err(err);
+        }
+        for (key, old, new) in applied {
+            if let Some((slot, scrub)) = old {
+                self.free_slot(slot, scrub)?;
+            }
+            match new {
+                Some(slot) => {
+                    Arc::make_mut(&mut self.indexmap).insert(key, slot);
+                }
+                None => {
+                    Arc::make_mut(&mut self.indexmap).remove(&key);
+                    self.invalidate(&key);
+                }
+            }
         }
         Ok(())
     }
-    // 压缩
-    fn compress(&mut self) -> Result<(),Error> {
-        let filelist = self.filepool.lock().unwrap().compress_filelist(ratio)?;
-        let mut realloclist:VecDeque<(u64,u32)> = VecDeque::new();
-        for fileid in filelist{
-            // 删除索引文件
-            self.filepool.lock().unwrap().removefile_withid(fileid,false)?;
-            // 已用大小,文件句柄
-            let  (endoff, file) = self.filepool.lock().unwrap().get_fileandfree(fileid)?;
-            let mut file = file;
-            realloclist.push_back((fileid,0));
-            // 记录文件
-            let recordfile = Recordfile::read_from(&mut file, fileid, endoff)?;
-            // 可写文件id,可写文件目前最大偏移
-            let (reallocfileid,offset) = realloclist.pop_front().unwrap();
-            let mut writefileid = reallocfileid;
-            let mut writeoffset = offset;
-            for record in recordfile.records {
-                if writeoffset + roundup(record.size(),16) as u32 >= max_filesize{
-                    let file_offset = realloclist.pop_front().unwrap();
-                    writefileid = file_offset.0; 
-                    writeoffset = file_offset.1;
-                }
-                self.writer.insert_record(writefileid, writeoffset, record)?;
+    // 为一次修改准备原位置的删除记录与新记录,不修改索引
+    // 删除记录的time早于新记录,恢复时新记录有效
+    fn stage(
+        &mut self,
+        key: &[u8],
+        value: Option<Vec<u8>>,
+    ) -> Result<(Retired<'a>, Staged<'a>), Error> {
+        let old = match self.indexmap.get(key).cloned() {
+            Some(slot) => {
+                let time = self.next_time()?;
+                let (tombstone, scrub) = self.prepare_tombstone(key, &slot, time)?;
+                Some((slot, tombstone, scrub))
+            }
+            None => None,
+        };
+        let new = match value {
+            Some(value) => {
+                let (record, slot) = self.prepare_record(key.to_vec(), value, NEVER_EXPIRE)?;
+                Some((slot, record))
             }
-            if writeoffset < max_filesize {
-                realloclist.push_front((writefileid,writeoffset));
+            None => None,
+        };
+        Ok((old, new))
+    }
+    // 撤销已准备的修改,归还新记录的空间并移除其缓存项
+    fn unstage(&mut self, staged: Vec<(Vec<u8>, Retired<'a>, Staged<'a>)>) -> Result<(), Error> {
+        for (key, _, new) in staged {
+            match new {
+                Some((slot, _)) => self.discard(&key, &slot)?,
+                None => self.invalidate(&key),
             }
-            // 先写index
-            self.writer.write_allIndex(true)?;
-            // 后写record
-            self.writer.write_all(true)?;
         }
         Ok(())
     }
+
+    // 同步所有写入过的文件并清空wal
+    pub fn sync_all(&mut self) -> Result<(), Error> {
+        self.writer.sync_all()
+    }
+    // 压缩所有待压缩的文件
+    pub fn compress(&mut self) -> Result<(), Error> {
+        let mut compaction = self.compaction()?;
+        while self.compact_next(&mut compaction)?.is_some() {}
+        Ok(())
+    }
+    // 选出本轮待压缩的文件,由compact_next逐个压缩
+    pub fn compaction(&mut self) -> Result<Compaction, Error> {
+        let now = get_timestamp()?;
+        let stats = self.file_stats();
+        let mut filelist = self.policy.select(&stats, now);
+        // 策略只能选择已封存的文件,快照仍在读取的文件与损坏的文件不能被压缩
+        let pinned = self.pinned_files();
+        filelist.retain(|fileid| {
+            !pinned.contains(fileid)
+                && !self.badfiles.contains(fileid)
+                && stats.iter().any(|file| file.fileid == *fileid)
+        });
+        filelist.sort();
+        filelist.dedup();
+        // 目标文件不在file_stats中,不会被选中,已被删除时不再作为目标
+        let target = self
+            .compaction_target
+            .filter(|target| self.filepool.lock().unwrap().has_file(*target));
+        Ok(Compaction {
+            files: filelist.into(),
+            target,
+            now,
+        })
+    }
+    // 已封存文件的统计信息,包括其中存活key的数量与范围
+    // 压缩的目标文件仍在被填充,与活跃文件一样不列出
+    pub fn file_stats(&self) -> Vec<FileStats> {
+        let mut stats = self.filepool.lock().unwrap().file_stats();
+        stats.retain(|file| Some(file.fileid) != self.compaction_target);
+        let positions: HashMap<Timestamp, usize> = stats
+            .iter()
+            .enumerate()
+            .map(|(position, file)| (file.fileid, position))
+            .collect();
+        let mut ranges: HashMap<Timestamp, (&[u8], &[u8])> = HashMap::new();
+        // 索引按key有序,第一个与最后一个key即为范围
+        for (key, slot) in self.indexmap.iter() {
+            if let Some(&position) = positions.get(&slot.fileid) {
+                stats[position].live_keys += 1;
+                ranges
+                    .entry(slot.fileid)
+                    .and_modify(|range| range.1 = key)
+                    .or_insert((key, key));
+            }
+        }
+        for (fileid, (smallest, largest)) in ranges {
+            let file = &mut stats[positions[&fileid]];
+            file.smallest_key = Some(smallest.to_vec());
+            file.largest_key = Some(largest.to_vec());
+        }
+        stats
+    }
+    // 压缩compaction中的下一个文件,返回读取的字节数,全部压缩完成时返回None
+    // 两次调用之间可以释放锁,期间被删除或被快照引用的文件会被跳过
+    // 只复制索引仍指向的记录,新位置同步落盘后才更新索引,最后删除原文件
+    // 存活且未过期的记录交给压缩过滤器,可以被保留、删除或替换value
+    pub fn compact_next(&mut self, compaction: &mut Compaction) -> Result<Option<u32>, Error> {
+        let fileid = match compaction.files.pop_front() {
+            Some(fileid) => fileid,
+            None => {
+                self.compactions.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
+        };
+        if !self.filepool.lock().unwrap().has_file(fileid) || self.pinned_files().contains(&fileid)
+        {
+            return Ok(Some(0));
+        }
+        let (endoff, file) = self.filepool.lock().unwrap().get_fileandfree(fileid)?;
+        let cipher = self.filepool.lock().unwrap().cipher();
+        let recordfile = Recordfile::read_from(&file, fileid, endoff, cipher.as_ref())?;
+        // 删除记录和已被覆盖的记录不再被索引引用,直接丢弃
+        let records: Vec<(u32, Record)> = recordfile
+            .records
+            .into_iter()
+            .filter(
+                |(offset, record)| match self.indexmap.get(record.key.as_ref()) {
+                    Some(slot) => slot.fileid == fileid && slot.offset == *offset,
+                    None => false,
+                },
+            )
+            .collect();
+        // 损坏的记录不在扫描结果中,索引仍指向它
+        // 此时删除文件会丢失该key,放弃压缩这个文件并记住它
+        let indexed = self
+            .indexmap
+            .values()
+            .filter(|slot| slot.fileid == fileid)
+            .count();
+        if records.len() != indexed {
+            self.badfiles.insert(fileid);
+            return Ok(Some(0));
+        }
+        let mut moved = Vec::new();
+        // 已过期或被过滤器删除的key
+        let mut removed = Vec::new();
+        for (offset, record) in records {
+            if record.is_expired(compaction.now) {
+                removed.push(record.key.to_vec());
+                continue;
+            }
+            let decision = match self.filter.as_ref() {
+                Some(filter) => filter.filter(&record.key, &record.value()?),
+                None => FilterDecision::Keep,
+            };
+            match decision {
+                FilterDecision::Keep => {}
+                // 原位置写入删除记录,删除原文件前崩溃时key不会复活
+                FilterDecision::Remove => {
+                    let time = self.next_time()?;
+                    let slot = Slot::new(
+                        offset,
+                        fileid,
+                        record.allocsize(),
+                        record.time,
+                        record.expiry,
+                    );
+                    let tombstone = Record::tombstone(record.key.to_vec(), time, self.encrypted);
+                    self.write_tombstone(&slot, tombstone)?;
+                    removed.push(record.key.to_vec());
+                    continue;
+                }
+                // 新value与写入一样重新压缩和分配
+                FilterDecision::ChangeValue(value) => {
+                    let key = record.key.to_vec();
+                    let (record, slot) = self.prepare_record(key.clone(), value, record.expiry)?;
+                    self.writer
+                        .insert_record(slot.fileid, slot.offset, record)?;
+                    moved.push((key, slot));
+                    continue;
+                }
+            }
+            // time不变,缓存项仍然有效
+            let (newfileid, newoffset) = self.allocate_target(compaction, record.allocsize())?;
+            let slot = Slot::new(
+                newoffset,
+                newfileid,
+                record.allocsize(),
+                record.time,
+                record.expiry,
+            );
+            moved.push((record.key.to_vec(), slot));
+            self.writer.insert_record(newfileid, newoffset, record)?;
+        }
+        self.writer.write_all(true)?;
+        // 记录已在新位置落盘,一次性切换索引
+        for key in removed.iter() {
+            self.invalidate(key);
+        }
+        let indexmap = Arc::make_mut(&mut self.indexmap);
+        for key in removed {
+            indexmap.remove(&key);
+        }
+        for (key, slot) in moved {
+            indexmap.insert(key, slot);
+        }
+        // 原文件中延迟释放的空间随文件一起删除
+        self.release_deferred()?;
+        self.deferred.retain(|(_, slot, _)| slot.fileid != fileid);
+        self.filepool.lock().unwrap().remove_datafile(fileid)?;
+        self.corrupt.remove(&fileid);
+        Ok(Some(endoff))
+    }
+    // 在本轮压缩的目标文件中分配空间,目标文件写满时新建
+    // 一轮压缩的多个文件因此合并为少数几个文件,不与活跃文件中的新写入混在一起
+    // 目标文件可能已被同时进行的另一轮压缩删除,此时同样新建
+    // 新建的目标文件记为compaction_target,下一轮先填满它的剩余空间
+    fn allocate_target(
+        &mut self,
+        compaction: &mut Compaction,
+        size: u32,
+    ) -> Result<(Timestamp, u32), Error> {
+        let mut filepool = self.filepool.lock().unwrap();
+        if let Some(target) = compaction.target {
+            match filepool.request_room_withid(size, target) {
+                Ok(offset) => return Ok((target, offset)),
+                Err(Error::Allocatefail(..)) | Err(Error::InvalidFileId(..)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        let target = filepool.create_datafile()?;
+        compaction.target = Some(target);
+        self.compaction_target = Some(target);
+        let offset = filepool.request_room_withid(size, target)?;
+        Ok((target, offset))
+    }
+    // 已封存文件中等待压缩的空间占已用空间的比例
+    pub fn dead_ratio(&self) -> f32 {
+        self.filepool.lock().unwrap().dead_ratio()
+    }
+}
+
+// 一轮压缩的进度
+#[derive(Debug)]
+pub struct Compaction {
+    // 尚未压缩的文件
+    files: VecDeque<Timestamp>,
+    // 存活记录被复制到的目标文件,沿用上一轮的目标文件,没有或写满时新建
+    target: Option<Timestamp>,
+    // 本轮压缩开始的时间,此时已过期的记录被丢弃
+    now: Timestamp,
+}
+
+// 恢复时同一key的记录a是否比b新,time相同时Put优先于Delete
+// 压缩复制的记录与原记录time相同,同time的Delete不能覆盖Put
+fn supersedes(a: &(Slot, RecordKind), b: &(Slot, RecordKind)) -> bool {
+    a.0.time > b.0.time
+        || (a.0.time == b.0.time && a.1 == RecordKind::Put && b.1 == RecordKind::Delete)
+}
+
+// 区间为空,起点大于终点时BTreeMap::range会panic
+fn is_empty_range(start: &Bound<Vec<u8>>, end: &Bound<Vec<u8>>) -> bool {
+    match (start, end) {
+        (Included(start), Included(end)) | (Included(start), Excluded(end)) => start > end,
+        (Excluded(start), Included(end)) | (Excluded(start), Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
+
+// 读取slot指向的记录
+// 快照读到的记录可能已被原地改写为保留value的Delete记录
+fn read_slot<'r>(files: &FileTable, slot: &Slot) -> Result<Record<'r>, Error> {
+    let file = files.get_file(slot.fileid)?;
+    let record = {
+        let _reading = files.iogate().read().unwrap();
+        Record::read_from(&file, slot.fileid, slot.offset, files.cipher())
+    }?;
+    record.ok_or_else(|| Error::InvalidKey("key in map but not in disk".to_string()))
+}
+
+// 用f处理slot指向的记录,已映射的文件中未加密的记录直接借用映射内存
+// 记录超出映射范围或文件未映射时按read_slot读取
+fn with_slot<F, T>(files: &FileTable, slot: &Slot, f: F) -> Result<T, Error>
+where
+    F: FnOnce(Record) -> Result<T, Error>,
+{
+    if let Some(map) = files.get_map(slot.fileid)? {
+        let _reading = files.iogate().read().unwrap();
+        match Record::read_from_map(&map, slot.fileid, slot.offset, files.cipher()) {
+            Ok(Some(record)) => return f(record),
+            Ok(None) => return Err(Error::InvalidKey("key in map but not in disk".to_string())),
+            Err(Error::Io(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => {}
+            Err(err) => return Err(err),
+        }
+    }
+    f(read_slot(files, slot)?)
+}
+
+// 按key顺序遍历一段索引,value在迭代到时才从.data文件读取
+// 迭代器持有创建时刻的视图,之后的写入不可见,已过期的key被跳过
+#[derive(Debug)]
+pub struct Iter {
+    view: Arc<View>,
+    files: Arc<FileTable>,
+    // 尚未遍历的区间
+    front: Bound<Vec<u8>>,
+    back: Bound<Vec<u8>>,
+}
+
+impl Iter {
+    pub fn new(
+        view: Arc<View>,
+        files: Arc<FileTable>,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    ) -> Iter {
+        Iter {
+            view,
+            files,
+            front: range.0,
+            back: range.1,
+        }
+    }
+
+    // 读取key-value
+    fn read(&self, key: Vec<u8>, slot: &Slot) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let value = with_slot(&self.files, slot, |record| record.into_value())?;
+        Ok((key, value))
+    }
+}
+
+impl Iterator for Iter {
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let now = match get_timestamp() {
+            Ok(now) => now,
+            Err(err) => return Some(Err(err)),
+        };
+        if is_empty_range(&self.front, &self.back) {
+            return None;
+        }
+        let (key, slot) = self
+            .view
+            .indexmap
+            .range((self.front.clone(), self.back.clone()))
+            .find(|(_, slot)| !slot.is_expired(now))
+            .map(|(key, slot)| (key.clone(), slot.clone()))?;
+        self.front = Excluded(key.clone());
+        Some(self.read(key, &slot))
+    }
+}
+
+impl DoubleEndedIterator for Iter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let now = match get_timestamp() {
+            Ok(now) => now,
+            Err(err) => return Some(Err(err)),
+        };
+        if is_empty_range(&self.front, &self.back) {
+            return None;
+        }
+        let (key, slot) = self
+            .view
+            .indexmap
+            .range((self.front.clone(), self.back.clone()))
+            .rfind(|(_, slot)| !slot.is_expired(now))
+            .map(|(key, slot)| (key.clone(), slot.clone()))?;
+        self.back = Excluded(key.clone());
+        Some(self.read(key, &slot))
+    }
+}
+
+// 快照冻结的索引
+#[derive(Debug)]
+pub struct View {
+    seq: u64,
+    indexmap: Arc<BTreeMap<Vec<u8>, Slot>>,
+}
+
+impl View {
+    pub fn get_value(&self, files: &FileTable, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        match self.indexmap.get(key) {
+            Some(slot) if !slot.is_expired(get_timestamp()?) => {
+                Ok(Some(with_slot(files, slot, |record| record.into_value())?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn get_version(&self, key: &[u8]) -> Option<Timestamp> {
+        self.indexmap.get(key).map(|slot| slot.time)
+    }
 }
 
 #[derive(Debug, Clone)]
 struct Slot {
     fileid: u64,
     offset: u32,
+    // 记录的分配大小
+    size: u32,
     time: Timestamp,
+    expiry: Timestamp,
 }
 impl Slot {
-    fn new(offset: u32, fileid: u64, time: Timestamp) -> Slot
+    fn new(offset: u32, fileid: u64, size: u32, time: Timestamp, expiry: Timestamp) -> Slot
 where {
         Slot {
-            fileid: fileid,
-            offset: offset,
-            time: time,
+            fileid,
+            offset,
+            size,
+            time,
+            expiry,
         }
     }
+    fn is_expired(&self, now: Timestamp) -> bool {
+        is_expired(self.expiry, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+
+    use super::*;
+    use std::sync::Barrier;
+    use std::thread;
+
+    #[test]
+    fn readers_do_not_wait_for_the_file_pool() {
+        let dir = tempfile::tempdir().unwrap();
+        let filepool = Arc::new(Mutex::new(FilePool::new(dir.path(), None, false).unwrap()));
+        let options = Options {
+            cache_capacity: 0,
+            ..Options::default()
+        };
+        let mut log = Log::open(filepool.clone(), &options).unwrap();
+        for i in 0..100u8 {
+            log.set(vec![i], vec![i; 100], NEVER_EXPIRE, true, false)
+                .unwrap();
+        }
+        // 写入者持有文件池的锁时,所有读者同时处于读取中才能通过barrier
+        let _allocating = filepool.lock().unwrap();
+        let readers = 4;
+        let barrier = Barrier::new(readers);
+        thread::scope(|scope| {
+            for reader in 0..readers as u8 {
+                let (log, barrier) = (&log, &barrier);
+                scope.spawn(move || {
+                    let value = log.get_with(&[reader], |value| {
+                        barrier.wait();
+                        value.to_vec()
+                    });
+                    assert_eq!(value.unwrap(), Some(vec![reader; 100]));
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn removed_files_are_counted_until_readers_release_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut filepool = FilePool::new(dir.path(), None, false).unwrap();
+        let table = filepool.table();
+        let first = filepool.create_datafile().unwrap();
+        let count = filepool.datafile_count();
+        assert_eq!(table.handle_count(), count);
+        // 读者仍持有被删除文件的句柄
+        let reading = table.get_file(first).unwrap();
+        filepool.remove_datafile(first).unwrap();
+        assert_eq!(filepool.datafile_count(), count - 1);
+        assert_eq!(table.handle_count(), count);
+        drop(reading);
+        assert_eq!(table.handle_count(), count - 1);
+    }
 }
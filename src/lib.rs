@@ -1,19 +1,44 @@
-#![feature(rustc_private)]
-#![feature(duration_extras)]
-
-
-
-extern crate indexmap;
 extern crate byteorder;
+extern crate chacha20poly1305;
+extern crate crc32c;
+extern crate lz4_flex;
+extern crate memmap2;
 
+mod batch;
+mod cache;
+mod compaction;
+mod compactor;
+mod compress;
+mod crypto;
 mod data;
-mod util;
-mod freelist;
+mod db;
+pub mod errors;
+mod eviction;
 mod filepool;
+mod freelist;
 mod index;
-mod cache;
-mod errors;
+mod options;
+mod snapshot;
+mod stats;
+mod transaction;
+mod util;
+mod wal;
 
+pub use batch::WriteBatch;
+pub use compaction::{
+    AgeBased, CompactionFilter, CompactionPolicy, DeadRatio, FileStats, FilterDecision, KeyRange,
+    SizeTiered,
+};
+pub use compress::Compression;
+pub use data::{FILE_HEADER_SIZE, FORMAT_VERSION, RECORD_HEADER_SIZE};
+pub use db::Db;
+pub use errors::Error;
+pub use eviction::CachePolicy;
+pub use index::Iter;
+pub use options::Options;
+pub use snapshot::Snapshot;
+pub use stats::Stats;
+pub use transaction::Transaction;
 
 #[cfg(test)]
 mod tests {
@@ -23,9 +48,8 @@ mod tests {
     fn it_works() {
         use std::collections::hash_map::DefaultHasher;
         let mut h = DefaultHasher::new();
-        &[11,2].hash(&mut h);
-        println!("{:?}",h.finish());
+        [11, 2].hash(&mut h);
+        println!("{:?}", h.finish());
         assert_eq!(2 + 2, 4);
     }
 }
- 
\ No newline at end of file
@@ -0,0 +1,93 @@
+use compaction::{CompactionFilter, CompactionPolicy, DeadRatio};
+use compress::Compression;
+use eviction::CachePolicy;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+// 默认的缓存容量,8MiB
+pub const DEFAULT_CACHE_CAPACITY: usize = 8 << 20;
+// 默认可以缓存的最大项,16MiB
+pub const DEFAULT_CACHE_MAX_ENTRY: usize = 16 << 20;
+// 默认每分钟后台压缩一次
+pub const DEFAULT_COMPACTION_INTERVAL: Duration = Duration::from_secs(60);
+// 默认在已封存文件一半的空间等待压缩时唤醒后台压缩
+pub const DEFAULT_COMPACTION_THRESHOLD: f32 = 0.5;
+// 默认后台压缩每秒最多读取16MiB
+pub const DEFAULT_COMPACTION_RATE: u64 = 16 << 20;
+
+// 打开数据库时的选项
+#[derive(Clone)]
+pub struct Options {
+    // 新写入value的压缩算法,默认不压缩
+    pub compression: Compression,
+    // 记录和.index文件的ChaCha20-Poly1305密钥,None时不加密
+    // 已有的加密记录需要同一个密钥才能读取
+    pub encryption_key: Option<[u8; 32]>,
+    // 读写时缓存的key与value的总字节数,0时不缓存
+    // 缓存项的权重固定为key与value的字节数,不提供自定义权重,容量因此总是内存上限
+    pub cache_capacity: usize,
+    // 需要缓存的最大一项的key与value字节数,缓存的分片不小于它
+    // 较大时分片较少,超过分片容量的项不被缓存
+    pub cache_max_entry: usize,
+    // 缓存的淘汰策略
+    pub cache_policy: CachePolicy,
+    // 只读映射不再追加记录的.data文件,读取时直接访问映射内存
+    pub mmap_sealed: bool,
+    // 打开时扫描到损坏的记录时继续打开,默认返回Error::Corruption
+    // 损坏记录的key被丢弃,其空间保留不用,数量见Stats::corrupt_records
+    pub repair: bool,
+    // 是否启动后台压缩线程,关闭时只能调用Db::compact_now压缩
+    pub background_compaction: bool,
+    // 后台压缩的定时间隔
+    pub compaction_interval: Duration,
+    // 已封存文件中等待压缩的空间比例达到该值时提前唤醒后台压缩
+    pub compaction_threshold: f32,
+    // 后台压缩每秒最多读取的字节数,0时不限速
+    pub compaction_rate: u64,
+    // 选择待压缩文件的策略,默认压缩四分之一以上空间等待压缩的文件
+    pub compaction_policy: Arc<dyn CompactionPolicy>,
+    // 压缩时对每条存活记录调用的过滤器,None时保留所有记录
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            compression: Compression::default(),
+            encryption_key: None,
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            cache_max_entry: DEFAULT_CACHE_MAX_ENTRY,
+            cache_policy: CachePolicy::default(),
+            mmap_sealed: false,
+            repair: false,
+            background_compaction: false,
+            compaction_interval: DEFAULT_COMPACTION_INTERVAL,
+            compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            compaction_rate: DEFAULT_COMPACTION_RATE,
+            compaction_policy: Arc::new(DeadRatio::default()),
+            compaction_filter: None,
+        }
+    }
+}
+
+// 不打印密钥
+impl fmt::Debug for Options {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Options")
+            .field("compression", &self.compression)
+            .field("encrypted", &self.encryption_key.is_some())
+            .field("cache_capacity", &self.cache_capacity)
+            .field("cache_max_entry", &self.cache_max_entry)
+            .field("cache_policy", &self.cache_policy)
+            .field("mmap_sealed", &self.mmap_sealed)
+            .field("repair", &self.repair)
+            .field("background_compaction", &self.background_compaction)
+            .field("compaction_interval", &self.compaction_interval)
+            .field("compaction_threshold", &self.compaction_threshold)
+            .field("compaction_rate", &self.compaction_rate)
+            .field("compaction_policy", &self.compaction_policy)
+            .field("compaction_filter", &self.compaction_filter)
+            .finish()
+    }
+}
@@ -0,0 +1,64 @@
+use errors::Error;
+use filepool::FileTable;
+use index::{Iter, View};
+use std::ops::Bound::{self, Included, Unbounded};
+use std::ops::RangeBounds;
+use std::sync::Arc;
+use util::{prefix_end, to_owned_bound, Timestamp};
+
+// 数据库在创建时刻的只读视图,之后的写入对快照不可见
+// 快照存活期间,其引用的记录空间不会被复用或压缩
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    view: Arc<View>,
+    files: Arc<FileTable>,
+}
+
+impl Snapshot {
+    pub fn new(view: Arc<View>, files: Arc<FileTable>) -> Snapshot {
+        Snapshot { view, files }
+    }
+
+    // 读取快照中key对应的value
+    pub fn get<K>(&self, key: K) -> Result<Option<Vec<u8>>, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.view.get_value(&self.files, key.as_ref())
+    }
+
+    // 快照中key记录的时间戳,用于事务的冲突检测
+    pub(crate) fn get_version(&self, key: &[u8]) -> Option<Timestamp> {
+        self.view.get_version(key)
+    }
+
+    // 按key顺序遍历快照中range内的key-value
+    pub fn range<K, R>(&self, range: R) -> Iter
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let start = to_owned_bound(range.start_bound());
+        let end = to_owned_bound(range.end_bound());
+        self.iter_range((start, end))
+    }
+
+    // 按key顺序遍历快照中所有以prefix开头的key-value
+    pub fn prefix<K>(&self, prefix: K) -> Iter
+    where
+        K: AsRef<[u8]>,
+    {
+        let prefix = prefix.as_ref();
+        self.iter_range((Included(prefix.to_vec()), prefix_end(prefix)))
+    }
+
+    // 按key顺序遍历快照中所有key-value
+    pub fn iter(&self) -> Iter {
+        self.iter_range((Unbounded, Unbounded))
+    }
+
+    // 迭代器共享快照的视图,可以比快照存活得更久
+    fn iter_range(&self, range: (Bound<Vec<u8>>, Bound<Vec<u8>>)) -> Iter {
+        Iter::new(self.view.clone(), self.files.clone(), range)
+    }
+}
@@ -0,0 +1,20 @@
+// 数据库运行时的统计信息
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    // 由缓存直接返回value的读取次数
+    pub cache_hits: u64,
+    // 需要读取.data文件的读取次数
+    pub cache_misses: u64,
+    // 完成的压缩轮数,包括后台压缩与Db::compact_now
+    pub compactions: u64,
+    // .data文件的数量
+    pub data_files: u64,
+    // 只读映射的已封存.data文件数量
+    pub mapped_files: u64,
+    // 打开时扫描到、空间仍被保留的损坏记录数量
+    pub corrupt_records: u64,
+    // 存活记录已损坏而不再被压缩的.data文件数量
+    pub bad_files: u64,
+    // 数据库持有的文件句柄数量,包括.data文件与wal
+    pub file_handles: u64,
+}
@@ -0,0 +1,72 @@
+use errors::Error;
+use snapshot::Snapshot;
+use std::collections::{BTreeMap, HashMap};
+use util::{check_key, Timestamp};
+
+// 读过的key及读到的记录时间戳,None表示key不存在
+pub type ReadSet = HashMap<Vec<u8>, Option<Timestamp>>;
+// 待提交的写入,value为None表示删除
+pub type WriteSet = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+// 乐观事务,读取基于开始时的快照,写入在提交前只保存在内存中
+// 提交时读过的key若已被改写,事务失败并返回Error::Conflict
+#[derive(Debug)]
+pub struct Transaction {
+    snapshot: Snapshot,
+    reads: ReadSet,
+    writes: WriteSet,
+}
+
+impl Transaction {
+    pub(crate) fn new(snapshot: Snapshot) -> Transaction {
+        Transaction {
+            snapshot,
+            reads: HashMap::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    // 读取key,可以读到事务自身的写入
+    pub fn get<K>(&mut self, key: K) -> Result<Option<Vec<u8>>, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        check_key(key)?;
+        if let Some(value) = self.writes.get(key) {
+            return Ok(value.clone());
+        }
+        let value = self.snapshot.get(key)?;
+        let version = self.snapshot.get_version(key);
+        self.reads.entry(key.to_vec()).or_insert(version);
+        Ok(value)
+    }
+
+    // 写入key-value,提交后生效
+    pub fn put<K, V>(&mut self, key: K, value: V) -> Result<(), Error>
+    where
+        Vec<u8>: From<K>,
+        Vec<u8>: From<V>,
+    {
+        let key = Vec::from(key);
+        check_key(&key)?;
+        self.writes.insert(key, Some(Vec::from(value)));
+        Ok(())
+    }
+
+    // 删除key,提交后生效
+    pub fn delete<K>(&mut self, key: K) -> Result<(), Error>
+    where
+        Vec<u8>: From<K>,
+    {
+        let key = Vec::from(key);
+        check_key(&key)?;
+        self.writes.insert(key, None);
+        Ok(())
+    }
+
+    // 拆分为读集合和写集合,快照随之释放
+    pub(crate) fn into_parts(self) -> (ReadSet, WriteSet) {
+        (self.reads, self.writes)
+    }
+}
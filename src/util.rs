@@ -1,25 +1,107 @@
 use errors::Error;
 use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
 use std::hash::{Hash, Hasher};
-use std::time::SystemTime;
+use std::io;
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
+use std::time::{SystemTime, UNIX_EPOCH};
 pub type Timestamp = u64;
 
-// 返回key的u64哈希值
-pub fn get_hash<T>(key: &T) -> u64
+// 返回任意可哈希值的u64哈希值
+pub fn hash_value<T>(value: &T) -> u64
 where
-    T: AsRef<[u8]>,
+    T: ?Sized + Hash,
 {
     let mut hasher = DefaultHasher::new();
-    let keyref: &[u8] = key.as_ref();
-    keyref.hash(&mut hasher);
+    value.hash(&mut hasher);
     hasher.finish()
 }
+// 从文件offset处读满buf,不使用也不改变文件的读写位置,可以在线程间共享文件
+#[cfg(unix)]
+pub fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+#[cfg(windows)]
+pub fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+// 将buf全部写到文件offset处
+#[cfg(unix)]
+pub fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+#[cfg(windows)]
+pub fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
 // 返回当前时间戳
 pub fn get_timestamp() -> Result<Timestamp, Error> {
-    let duration = SystemTime::now().elapsed()?;
+    let duration = SystemTime::now().duration_since(UNIX_EPOCH)?;
     Ok(duration.as_secs() * 1000 + duration.subsec_millis() as u64)
 }
+// 将size向上对齐到base的整数倍
 #[inline]
 pub fn roundup(size: usize, base: usize) -> usize {
-    (size+base-1)/base
+    size.div_ceil(base) * base
+}
+
+// 将借用的区间端点转为持有所有权的端点
+pub fn to_owned_bound<K>(bound: Bound<&K>) -> Bound<Vec<u8>>
+where
+    K: AsRef<[u8]>,
+{
+    match bound {
+        Included(key) => Included(key.as_ref().to_vec()),
+        Excluded(key) => Excluded(key.as_ref().to_vec()),
+        Unbounded => Unbounded,
+    }
+}
+
+// 大于所有以prefix开头的key的最小key
+pub fn prefix_end(prefix: &[u8]) -> Bound<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Excluded(end);
+        }
+    }
+    Unbounded
+}
+
+// key不能为空,且长度不能超过u16
+pub fn check_key(key: &[u8]) -> Result<(), Error> {
+    if key.is_empty() {
+        Err(Error::InvalidKey("key is empty".to_string()))
+    } else if key.len() > u16::MAX as usize {
+        Err(Error::InvalidKey("key is longer than u16::MAX".to_string()))
+    } else {
+        Ok(())
+    }
 }
@@ -0,0 +1,210 @@
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use crc32c::crc32c;
+use data::FILE_HEADER_SIZE;
+use errors::Error;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use util::Timestamp;
+
+// wal中的一次写入: 将bytes写到fileid文件的offset处
+#[derive(Debug, Clone)]
+pub struct WalEntry {
+    pub fileid: Timestamp,
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl WalEntry {
+    fn size(&self) -> usize {
+        8 + 4 + 4 + self.bytes.len()
+    }
+}
+
+// wal中帧内的项
+enum WalItem {
+    // 批次开始,count为批次中的写入数
+    Begin { batchid: u64, count: u32 },
+    Write(WalEntry),
+    // 批次提交,之前的写入全部生效
+    Commit { batchid: u64 },
+}
+
+const ITEM_BEGIN: u8 = 1;
+const ITEM_WRITE: u8 = 2;
+const ITEM_COMMIT: u8 = 3;
+// 单帧payload的最大长度,大批次被拆分为多帧
+const MAX_FRAMESIZE: usize = 1 << 20;
+
+// 预写日志,每批写入以Begin开始,Commit结束,可能跨越多帧
+// 帧: payloadsize(4) crc(4) payload, payload由若干项组成
+// Begin: kind(1) batchid(8) count(4)
+// Write: kind(1) fileid(8) offset(4) size(4) bytes
+// Commit: kind(1) batchid(8)
+// crc为payload的crc32c校验和,不完整或校验失败的帧及其之后的内容被丢弃
+// 没有对应Commit的批次在重放时被整体丢弃
+#[derive(Debug)]
+pub struct Wal {
+    file: File,
+    // 文件当前长度,包括文件头
+    size: u64,
+    // 最近一个批次的id
+    lastbatch: u64,
+}
+
+impl Wal {
+    // file为已检查过文件头的wal文件
+    pub fn new(file: File) -> Result<Wal, Error> {
+        let size = file.metadata()?.len();
+        Ok(Wal {
+            file,
+            size,
+            lastbatch: 0,
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    // 将entries作为一个批次追加并同步,返回后这批写入不会因崩溃丢失
+    pub fn append(&mut self, entries: &[WalEntry]) -> Result<(), Error> {
+        self.lastbatch += 1;
+        let batchid = self.lastbatch;
+        let mut buf = Vec::new();
+        let mut payload = Vec::new();
+        payload.write_u8(ITEM_BEGIN)?;
+        payload.write_u64::<LittleEndian>(batchid)?;
+        payload.write_u32::<LittleEndian>(entries.len() as u32)?;
+        for entry in entries {
+            if payload.len() + 1 + entry.size() > MAX_FRAMESIZE {
+                push_frame(&mut buf, &payload)?;
+                payload.clear();
+            }
+            payload.write_u8(ITEM_WRITE)?;
+            payload.write_u64::<LittleEndian>(entry.fileid)?;
+            payload.write_u32::<LittleEndian>(entry.offset)?;
+            payload.write_u32::<LittleEndian>(entry.bytes.len() as u32)?;
+            payload.write_all(&entry.bytes)?;
+        }
+        payload.write_u8(ITEM_COMMIT)?;
+        payload.write_u64::<LittleEndian>(batchid)?;
+        push_frame(&mut buf, &payload)?;
+        if let Err(err) = self.write_frames(&buf) {
+            // 丢弃写了一半的批次,之后的批次从原位置开始写
+            self.file.set_len(self.size)?;
+            return Err(err);
+        }
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    fn write_frames(&mut self, buf: &[u8]) -> Result<(), Error> {
+        self.file.seek(SeekFrom::Start(self.size))?;
+        self.file.write_all(buf)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    // 按顺序读取所有已提交批次中的写入
+    pub fn read_all(&mut self) -> Result<Vec<WalEntry>, Error> {
+        let mut entries = Vec::new();
+        // 正在读取的批次: batchid, 写入数, 已读取的写入
+        let mut batch: Option<(u64, u32, Vec<WalEntry>)> = None;
+        self.file.seek(SeekFrom::Start(FILE_HEADER_SIZE))?;
+        let mut reader = BufReader::new(&mut self.file);
+        'frames: loop {
+            let mut header = [0; 8];
+            match reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(Error::Io(err)),
+            }
+            let payloadsize = LittleEndian::read_u32(&header[0..4]) as u64;
+            let crc = LittleEndian::read_u32(&header[4..8]);
+            // 帧长度超出文件末尾时不分配缓冲区
+            if payloadsize > self.size {
+                break;
+            }
+            let mut payload = vec![0; payloadsize as usize];
+            match reader.read_exact(&mut payload) {
+                Ok(()) => {}
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(Error::Io(err)),
+            }
+            if crc32c(&payload) != crc {
+                break;
+            }
+            let mut cursor = Cursor::new(payload);
+            while (cursor.position() as usize) < cursor.get_ref().len() {
+                match read_item(&mut cursor) {
+                    Ok(WalItem::Begin { batchid, count }) => {
+                        batch = Some((batchid, count, Vec::new()));
+                    }
+                    Ok(WalItem::Write(entry)) => {
+                        if let Some((_, _, ref mut writes)) = batch {
+                            writes.push(entry);
+                        }
+                    }
+                    Ok(WalItem::Commit { batchid }) => match batch.take() {
+                        Some((id, count, writes))
+                            if id == batchid && writes.len() == count as usize =>
+                        {
+                            entries.extend(writes)
+                        }
+                        _ => {}
+                    },
+                    Err(_) => break 'frames,
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    // 清空文件头之后的内容
+    pub fn truncate(&mut self) -> Result<(), Error> {
+        self.file.set_len(FILE_HEADER_SIZE)?;
+        self.file.sync_all()?;
+        self.size = FILE_HEADER_SIZE;
+        Ok(())
+    }
+}
+
+// 将payload作为一帧追加到buf
+fn push_frame(buf: &mut Vec<u8>, payload: &[u8]) -> io::Result<()> {
+    buf.write_u32::<LittleEndian>(payload.len() as u32)?;
+    buf.write_u32::<LittleEndian>(crc32c(payload))?;
+    buf.write_all(payload)
+}
+
+fn read_item(cursor: &mut Cursor<Vec<u8>>) -> io::Result<WalItem> {
+    match cursor.read_u8()? {
+        ITEM_BEGIN => {
+            let batchid = cursor.read_u64::<LittleEndian>()?;
+            let count = cursor.read_u32::<LittleEndian>()?;
+            Ok(WalItem::Begin { batchid, count })
+        }
+        ITEM_WRITE => {
+            let fileid = cursor.read_u64::<LittleEndian>()?;
+            let offset = cursor.read_u32::<LittleEndian>()?;
+            let size = cursor.read_u32::<LittleEndian>()? as usize;
+            if size > cursor.get_ref().len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "bad wal entry"));
+            }
+            let mut bytes = vec![0; size];
+            cursor.read_exact(&mut bytes)?;
+            Ok(WalItem::Write(WalEntry {
+                fileid,
+                offset,
+                bytes,
+            }))
+        }
+        ITEM_COMMIT => {
+            let batchid = cursor.read_u64::<LittleEndian>()?;
+            Ok(WalItem::Commit { batchid })
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unknown wal item",
+        )),
+    }
+}
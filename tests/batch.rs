@@ -0,0 +1,186 @@
+extern crate koundb;
+extern crate tempfile;
+
+mod common;
+
+use common::datafiles;
+use koundb::{Db, Error, WriteBatch};
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+
+// 保存所有.data文件的内容
+fn save_datafiles(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    datafiles(dir)
+        .into_iter()
+        .map(|path| {
+            let bytes = fs::read(&path).unwrap();
+            (path, bytes)
+        })
+        .collect()
+}
+
+// 恢复.data文件,模拟写.data文件前崩溃
+fn restore_datafiles(dir: &Path, saved: &[(PathBuf, Vec<u8>)]) {
+    for path in datafiles(dir) {
+        if !saved.iter().any(|(saved, _)| *saved == path) {
+            fs::remove_file(path).unwrap();
+        }
+    }
+    for (path, bytes) in saved {
+        fs::write(path, bytes).unwrap();
+    }
+}
+
+// 写入一个跨越多个wal帧的批次后崩溃,返回崩溃前的.data文件内容
+fn crash_after_batch(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let db = Db::open(dir).unwrap();
+    db.put("old", "value").unwrap();
+    db.put("gone", "value").unwrap();
+    db.close().unwrap();
+    let saved = save_datafiles(dir);
+
+    let db = Db::open(dir).unwrap();
+    let mut batch = WriteBatch::new();
+    for i in 0..8u8 {
+        batch.put(format!("key{}", i), vec![i; 300 * 1024]);
+    }
+    batch.put("old", "new");
+    batch.delete("gone");
+    db.write(batch).unwrap();
+    drop(db);
+    saved
+}
+
+#[test]
+fn batch_applies_all_ops() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("a", "1").unwrap();
+    db.put("b", "2").unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch.put("a", "10");
+    batch.delete("b");
+    batch.put("c", "3");
+    batch.delete("missing");
+    assert_eq!(batch.len(), 4);
+    db.write(batch).unwrap();
+
+    assert_eq!(db.get("a").unwrap(), Some(b"10".to_vec()));
+    assert_eq!(db.get("b").unwrap(), None);
+    assert_eq!(db.get("c").unwrap(), Some(b"3".to_vec()));
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("a").unwrap(), Some(b"10".to_vec()));
+    assert_eq!(db.get("b").unwrap(), None);
+    assert_eq!(db.get("c").unwrap(), Some(b"3".to_vec()));
+}
+
+#[test]
+fn last_op_on_key_wins() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", "old").unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch.put("key", "first");
+    batch.delete("key");
+    batch.put("key", "second");
+    batch.put("other", "value");
+    batch.delete("other");
+    assert_eq!(batch.len(), 2);
+    db.write(batch).unwrap();
+
+    assert_eq!(db.get("key").unwrap(), Some(b"second".to_vec()));
+    assert_eq!(db.get("other").unwrap(), None);
+}
+
+#[test]
+fn invalid_key_rejects_whole_batch() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch.put("key", "value");
+    batch.put("", "value");
+    match db.write(batch) {
+        Err(Error::InvalidKey(..)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(db.get("key").unwrap(), None);
+}
+
+#[test]
+fn committed_batch_is_replayed() {
+    let dir = tempfile::tempdir().unwrap();
+    let saved = crash_after_batch(dir.path());
+    restore_datafiles(dir.path(), &saved);
+
+    let db = Db::open(dir.path()).unwrap();
+    for i in 0..8u8 {
+        assert_eq!(
+            db.get(format!("key{}", i)).unwrap(),
+            Some(vec![i; 300 * 1024])
+        );
+    }
+    assert_eq!(db.get("old").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(db.get("gone").unwrap(), None);
+}
+
+#[test]
+fn partial_batch_is_discarded() {
+    let dir = tempfile::tempdir().unwrap();
+    let saved = crash_after_batch(dir.path());
+    restore_datafiles(dir.path(), &saved);
+
+    // 批次的最后一帧没有写完
+    let path = dir.path().join("koundb.wal");
+    let len = fs::metadata(&path).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len - 10).unwrap();
+    drop(file);
+
+    let db = Db::open(dir.path()).unwrap();
+    for i in 0..8u8 {
+        assert_eq!(db.get(format!("key{}", i)).unwrap(), None);
+    }
+    assert_eq!(db.get("old").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(db.get("gone").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn failed_batch_leaves_no_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("a", "1").unwrap();
+    db.put("b", "2").unwrap();
+
+    // 按key排序,超大的记录在其余修改都分配空间之后才失败
+    let mut batch = WriteBatch::new();
+    batch.put("a", "10");
+    batch.delete("b");
+    batch.put("c", "3");
+    batch.put("z", vec![0u8; 33 << 20]);
+    match db.write(batch) {
+        Err(Error::Allocatefail(_)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(db.get("a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(db.get("b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(db.get("c").unwrap(), None);
+
+    // 失败的批次不会残留在下一个批次中
+    let mut batch = WriteBatch::new();
+    batch.put("d", "4");
+    db.write(batch).unwrap();
+    assert_eq!(db.get("a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(db.get("c").unwrap(), None);
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(db.get("b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(db.get("c").unwrap(), None);
+    assert_eq!(db.get("d").unwrap(), Some(b"4".to_vec()));
+}
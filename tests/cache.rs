@@ -0,0 +1,151 @@
+extern crate koundb;
+extern crate tempfile;
+
+use koundb::{CachePolicy, Db, Options};
+
+// 缓存命中与未命中次数
+fn cache_stats(db: &Db) -> (u64, u64) {
+    let stats = db.stats();
+    (stats.cache_hits, stats.cache_misses)
+}
+
+#[test]
+fn reads_are_served_from_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", "value").unwrap();
+    // 写入时填充缓存
+    assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(cache_stats(&db), (1, 0));
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(db.get("missing").unwrap(), None);
+    assert_eq!(cache_stats(&db), (1, 1));
+}
+
+#[test]
+fn writes_and_deletes_update_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", "old").unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"old".to_vec()));
+    db.put("key", "new").unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"new".to_vec()));
+    db.delete("key").unwrap();
+    assert_eq!(db.get("key").unwrap(), None);
+    db.put("key", "again").unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"again".to_vec()));
+    assert_eq!(cache_stats(&db), (3, 0));
+}
+
+#[test]
+fn capacity_bounds_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    // 容量按key与value的字节数计算,只放得下两项
+    let options = Options {
+        cache_capacity: 4,
+        ..Options::default()
+    };
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    for key in &["a", "b", "c"] {
+        db.put(*key, *key).unwrap();
+    }
+    assert_eq!(db.get("a").unwrap(), Some(b"a".to_vec()));
+    assert_eq!(db.get("a").unwrap(), Some(b"a".to_vec()));
+    assert_eq!(cache_stats(&db), (1, 1));
+}
+
+#[test]
+fn large_values_are_not_cached() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        cache_capacity: 100,
+        ..Options::default()
+    };
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    db.put("small", "value").unwrap();
+    db.put("large", vec![7u8; 200]).unwrap();
+    assert_eq!(db.get("large").unwrap(), Some(vec![7u8; 200]));
+    assert_eq!(db.get("small").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(cache_stats(&db), (1, 1));
+}
+
+#[test]
+fn every_policy_serves_reads() {
+    for &policy in &[CachePolicy::Lru, CachePolicy::Clock, CachePolicy::TinyLfu] {
+        // 每项14字节,700字节时需要淘汰,2000字节时放得下全部
+        for &capacity in &[700, 2000] {
+            let dir = tempfile::tempdir().unwrap();
+            let options = Options {
+                cache_capacity: capacity,
+                cache_policy: policy,
+                ..Options::default()
+            };
+            let db = Db::open_with_options(dir.path(), options).unwrap();
+            for i in 0..100 {
+                db.put(format!("key{:03}", i), format!("value{:03}", i))
+                    .unwrap();
+            }
+            for _ in 0..3 {
+                for i in 0..100 {
+                    let value = db.get(format!("key{:03}", i)).unwrap();
+                    assert_eq!(value, Some(format!("value{:03}", i).into_bytes()));
+                }
+            }
+            let stats = db.stats();
+            assert_eq!(stats.cache_hits + stats.cache_misses, 300);
+            if capacity == 2000 {
+                assert_eq!(stats.cache_hits, 300, "{:?}", policy);
+            }
+        }
+    }
+}
+
+#[test]
+fn zero_capacity_disables_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        cache_capacity: 0,
+        ..Options::default()
+    };
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    db.put("key", "value").unwrap();
+    for _ in 0..3 {
+        assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+    }
+    assert_eq!(cache_stats(&db), (0, 3));
+}
+
+#[test]
+fn hot_key_survives_eviction() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        cache_capacity: 30,
+        ..Options::default()
+    };
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    // hot占8字节,每个cold占11字节,缓存中最多有hot与两个cold
+    db.put("hot", "value").unwrap();
+    // 每次写入新key前读取hot,hot始终不是最久未访问的key
+    for i in 0..20 {
+        assert_eq!(db.get("hot").unwrap(), Some(b"value".to_vec()));
+        db.put(format!("cold{:02}", i), "value").unwrap();
+    }
+    assert_eq!(cache_stats(&db), (20, 0));
+    assert_eq!(db.get("cold00").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(cache_stats(&db), (20, 1));
+}
+
+#[test]
+fn values_larger_than_a_mebibyte_are_cached() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    let value = vec![7u8; 2 << 20];
+    db.put("large", value.clone()).unwrap();
+    assert_eq!(db.get("large").unwrap(), Some(value.clone()));
+    assert_eq!(db.get("large").unwrap(), Some(value));
+    assert_eq!(cache_stats(&db), (2, 0));
+}
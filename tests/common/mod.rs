@@ -0,0 +1,39 @@
+// 集成测试共用的文件检查函数,每个测试只用到其中一部分
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// 目录中指定扩展名的文件,按文件id排序
+pub fn files_with_extension(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+    files.sort();
+    files
+}
+
+// 目录中的所有.data文件,按文件id排序
+pub fn datafiles(dir: &Path) -> Vec<PathBuf> {
+    files_with_extension(dir, "data")
+}
+
+// 目录中最早的.data文件
+pub fn datafile(dir: &Path) -> PathBuf {
+    datafiles(dir).remove(0)
+}
+
+// 所有.data文件的总大小
+pub fn datasize(dir: &Path) -> u64 {
+    datafiles(dir)
+        .iter()
+        .map(|path| fs::metadata(path).unwrap().len())
+        .sum()
+}
+
+// 文件名中的文件id
+pub fn fileid(path: &Path) -> u64 {
+    path.file_stem().unwrap().to_str().unwrap().parse().unwrap()
+}
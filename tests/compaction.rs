@@ -0,0 +1,621 @@
+extern crate koundb;
+extern crate tempfile;
+
+mod common;
+
+use common::{datafiles, files_with_extension};
+use koundb::{
+    CompactionFilter, CompactionPolicy, Db, Error, FileStats, FilterDecision, KeyRange, Options,
+    SizeTiered,
+};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn background(interval: Duration, threshold: f32) -> Options {
+    Options {
+        background_compaction: true,
+        compaction_interval: interval,
+        compaction_threshold: threshold,
+        ..Options::default()
+    }
+}
+
+// 等待后台压缩完成至少count轮
+fn wait_for_compactions(db: &Db, count: u64) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if db.stats().compactions >= count {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+// 足以让第一个.data文件封存的value大小与数量
+const VALUE_SIZE: usize = 1 << 20;
+const COUNT: u32 = 40;
+
+fn value(i: u32) -> Vec<u8> {
+    vec![i as u8; VALUE_SIZE]
+}
+
+// 写满第一个文件,删除并覆盖其中一半的key
+fn fill_with_dead_space(db: &Db) {
+    for i in 0..COUNT {
+        db.put(format!("key{}", i), value(i)).unwrap();
+    }
+    for i in (0..20).filter(|i| i % 2 == 0) {
+        db.delete(format!("key{}", i)).unwrap();
+    }
+    for i in (0..20).filter(|i| i % 4 == 1) {
+        db.put(format!("key{}", i), value(i + 100)).unwrap();
+    }
+}
+
+fn expected(i: u32) -> Option<Vec<u8>> {
+    match i {
+        i if i < 20 && i % 2 == 0 => None,
+        i if i < 20 && i % 4 == 1 => Some(value(i + 100)),
+        i => Some(value(i)),
+    }
+}
+
+fn check(db: &Db) {
+    for i in 0..COUNT {
+        assert_eq!(
+            db.get(format!("key{}", i)).unwrap(),
+            expected(i),
+            "key{}",
+            i
+        );
+    }
+}
+
+#[test]
+fn compaction_moves_live_records_and_deletes_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = Db::open(dir.path()).unwrap();
+        fill_with_dead_space(&db);
+        let before = datafiles(dir.path());
+        assert!(before.len() > 1);
+        db.compact_now().unwrap();
+        let after = datafiles(dir.path());
+        assert!(!after.contains(&before[0]));
+        check(&db);
+        let keys = db.iter().count();
+        assert_eq!(keys, COUNT as usize - 10);
+        db.close().unwrap();
+    }
+    // 删除的key不会在重新打开后复活
+    let db = Db::open(dir.path()).unwrap();
+    check(&db);
+}
+
+fn remove_indexfiles(dir: &Path) {
+    for path in files_with_extension(dir, "index") {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn crash_between_sync_and_delete_keeps_every_key() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = Db::open(dir.path()).unwrap();
+        fill_with_dead_space(&db);
+        db.close().unwrap();
+    }
+    let source = datafiles(dir.path())[0].clone();
+    let bytes = fs::read(&source).unwrap();
+    {
+        let db = Db::open(dir.path()).unwrap();
+        db.compact_now().unwrap();
+        assert!(!source.exists());
+        db.close().unwrap();
+    }
+    // 模拟复制落盘后、删除原文件前崩溃: 原文件与目标文件同时存在
+    fs::write(&source, &bytes).unwrap();
+    remove_indexfiles(dir.path());
+    for _ in 0..3 {
+        let db = Db::open(dir.path()).unwrap();
+        check(&db);
+        db.compact_now().unwrap();
+        check(&db);
+        db.close().unwrap();
+        remove_indexfiles(dir.path());
+    }
+    let db = Db::open(dir.path()).unwrap();
+    check(&db);
+}
+
+#[test]
+fn compaction_keeps_files_with_corrupt_live_records() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = Db::open(dir.path()).unwrap();
+        fill_with_dead_space(&db);
+        db.close().unwrap();
+    }
+    // 在key3的value中间翻转一个字节,打开时使用.index文件,压缩扫描时才发现这条记录已损坏
+    let source = datafiles(dir.path())[0].clone();
+    let mut bytes = fs::read(&source).unwrap();
+    let start = bytes
+        .windows(64)
+        .position(|window| window.iter().all(|byte| *byte == 3))
+        .unwrap();
+    bytes[start + VALUE_SIZE / 2] ^= 0xff;
+    fs::write(&source, &bytes).unwrap();
+
+    let options = Options {
+        cache_capacity: 0,
+        ..Options::default()
+    };
+    let db = Db::open_with_options(dir.path(), options.clone()).unwrap();
+    db.compact_now().unwrap();
+    assert!(source.exists());
+    assert_eq!(db.stats().bad_files, 1);
+    // 损坏的文件不再被选中,下一轮压缩不再读取它
+    db.compact_now().unwrap();
+    assert!(source.exists());
+    assert_eq!(db.stats().bad_files, 1);
+    match db.get("key3") {
+        Err(Error::Corruption { .. }) => {}
+        other => panic!(
+            "unexpected result: {:?}",
+            other.map(|value| value.map(|v| v.len()))
+        ),
+    }
+    for i in (0..COUNT).filter(|i| *i != 3) {
+        assert_eq!(
+            db.get(format!("key{}", i)).unwrap(),
+            expected(i),
+            "key{}",
+            i
+        );
+    }
+    db.close().unwrap();
+
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    assert!(db.get("key3").is_err());
+}
+
+#[test]
+fn compaction_skips_files_read_by_snapshots() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    fill_with_dead_space(&db);
+    let first = datafiles(dir.path())[0].clone();
+    let snapshot = db.snapshot();
+    db.compact_now().unwrap();
+    assert!(first.exists());
+    assert_eq!(snapshot.get("key3").unwrap(), Some(value(3)));
+    drop(snapshot);
+    db.compact_now().unwrap();
+    assert!(!first.exists());
+    check(&db);
+}
+
+#[test]
+fn compaction_without_dead_space_keeps_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    for i in 0..COUNT {
+        db.put(format!("key{}", i), value(i)).unwrap();
+    }
+    let before = datafiles(dir.path());
+    db.compact_now().unwrap();
+    assert_eq!(datafiles(dir.path()), before);
+}
+
+// 记录收到的统计信息,不选择任何文件
+#[derive(Debug, Default)]
+struct Recording {
+    seen: Mutex<Vec<FileStats>>,
+}
+
+impl CompactionPolicy for Recording {
+    fn select(&self, files: &[FileStats], _now: u64) -> Vec<u64> {
+        *self.seen.lock().unwrap() = files.to_vec();
+        Vec::new()
+    }
+}
+
+// 选择所有文件,并记录每次被提供的文件id
+#[derive(Debug, Default)]
+struct SelectAll {
+    offered: Mutex<Vec<Vec<u64>>>,
+}
+
+impl CompactionPolicy for SelectAll {
+    fn select(&self, files: &[FileStats], _now: u64) -> Vec<u64> {
+        let fileids: Vec<u64> = files.iter().map(|file| file.fileid).collect();
+        self.offered.lock().unwrap().push(fileids.clone());
+        fileids
+    }
+}
+
+fn with_policy(policy: Arc<dyn CompactionPolicy>) -> Options {
+    Options {
+        compaction_policy: policy,
+        ..Options::default()
+    }
+}
+
+#[test]
+fn policy_receives_stats_of_sealed_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let policy = Arc::new(Recording::default());
+    let db = Db::open_with_options(dir.path(), with_policy(policy.clone())).unwrap();
+    fill_with_dead_space(&db);
+    let before = datafiles(dir.path());
+    db.compact_now().unwrap();
+    assert_eq!(datafiles(dir.path()), before);
+    let seen = policy.seen.lock().unwrap().clone();
+    // 活跃文件不在统计之内
+    assert_eq!(seen.len(), before.len() - 1);
+    let first = &seen[0];
+    assert!(first.dead > 0 && first.dead < first.used);
+    assert!(first.live_keys > 0);
+    assert_eq!(first.smallest_key, Some(b"key11".to_vec()));
+    assert!(first.smallest_key <= first.largest_key);
+}
+
+#[test]
+fn key_range_policy_compacts_only_overlapping_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let policy = KeyRange {
+        start: b"zzz".to_vec(),
+        end: None,
+    };
+    let db = Db::open_with_options(dir.path(), with_policy(Arc::new(policy))).unwrap();
+    for i in 0..COUNT {
+        db.put(format!("key{}", i), value(i)).unwrap();
+    }
+    let before = datafiles(dir.path());
+    db.compact_now().unwrap();
+    assert_eq!(datafiles(dir.path()), before);
+    db.close().unwrap();
+
+    let policy = KeyRange {
+        start: b"key1".to_vec(),
+        end: Some(b"key2".to_vec()),
+    };
+    let db = Db::open_with_options(dir.path(), with_policy(Arc::new(policy))).unwrap();
+    db.compact_now().unwrap();
+    assert!(!before[0].exists());
+    for i in 0..COUNT {
+        assert_eq!(db.get(format!("key{}", i)).unwrap(), Some(value(i)));
+    }
+}
+
+#[test]
+fn size_tiered_policy_waits_for_enough_small_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let policy = SizeTiered {
+        max_live: 1 << 20,
+        min_files: 2,
+    };
+    let db = Db::open_with_options(dir.path(), with_policy(Arc::new(policy))).unwrap();
+    fill_with_dead_space(&db);
+    let before = datafiles(dir.path());
+    db.compact_now().unwrap();
+    assert_eq!(datafiles(dir.path()), before);
+    check(&db);
+}
+
+// 删除序号为3的倍数的key,替换余1的key的value,记录见过的key
+#[derive(Debug, Default)]
+struct Upgrade {
+    seen: Mutex<Vec<u32>>,
+}
+
+fn index(key: &[u8]) -> u32 {
+    String::from_utf8_lossy(&key[3..]).parse().unwrap()
+}
+
+impl CompactionFilter for Upgrade {
+    fn filter(&self, key: &[u8], value: &[u8]) -> FilterDecision {
+        let i = index(key);
+        assert_eq!(value, &self::value(i)[..]);
+        self.seen.lock().unwrap().push(i);
+        match i % 3 {
+            0 => FilterDecision::Remove,
+            1 => FilterDecision::ChangeValue(format!("upgraded{}", i).into_bytes()),
+            _ => FilterDecision::Keep,
+        }
+    }
+}
+
+#[test]
+fn filter_removes_and_rewrites_live_records() {
+    let dir = tempfile::tempdir().unwrap();
+    let filter = Arc::new(Upgrade::default());
+    let options = Options {
+        compaction_policy: Arc::new(KeyRange {
+            start: Vec::new(),
+            end: None,
+        }),
+        compaction_filter: Some(filter.clone()),
+        ..Options::default()
+    };
+    let filtered = |i: u32| match i % 3 {
+        0 => None,
+        1 => Some(format!("upgraded{}", i).into_bytes()),
+        _ => Some(value(i)),
+    };
+    {
+        let db = Db::open_with_options(dir.path(), options.clone()).unwrap();
+        for i in 0..COUNT {
+            db.put(format!("key{}", i), value(i)).unwrap();
+        }
+        db.delete("key2").unwrap();
+        db.compact_now().unwrap();
+        let seen = filter.seen.lock().unwrap().clone();
+        assert!(!seen.is_empty());
+        // 已删除的key不会经过过滤器
+        assert!(!seen.contains(&2));
+        for i in 0..COUNT {
+            let expected = match i {
+                2 => None,
+                i if seen.contains(&i) => filtered(i),
+                i => Some(value(i)),
+            };
+            assert_eq!(db.get(format!("key{}", i)).unwrap(), expected, "key{}", i);
+        }
+        db.close().unwrap();
+    }
+    let seen = filter.seen.lock().unwrap().clone();
+    let db = Db::open(dir.path()).unwrap();
+    for i in seen {
+        assert_eq!(
+            db.get(format!("key{}", i)).unwrap(),
+            filtered(i),
+            "key{}",
+            i
+        );
+    }
+}
+
+// 删除指定的key,其余记录原样保留
+#[derive(Debug)]
+struct RemoveKey(&'static str);
+
+impl CompactionFilter for RemoveKey {
+    fn filter(&self, key: &[u8], _value: &[u8]) -> FilterDecision {
+        if key == self.0.as_bytes() {
+            FilterDecision::Remove
+        } else {
+            FilterDecision::Keep
+        }
+    }
+}
+
+#[test]
+fn filter_removal_survives_crash_before_file_delete() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        compaction_filter: Some(Arc::new(RemoveKey("small3"))),
+        ..Options::default()
+    };
+    let db = Db::open_with_options(dir.path(), options.clone()).unwrap();
+    // 大value写满第一个文件后删除,压缩时只复制少量小记录,wal不会被清空
+    db.put("filler", vec![0u8; 31 << 20]).unwrap();
+    for i in 0..8 {
+        db.put(format!("small{}", i), format!("value{}", i))
+            .unwrap();
+    }
+    db.put("next", vec![1u8; 2 << 20]).unwrap();
+    db.delete("filler").unwrap();
+    let source = datafiles(dir.path())[0].clone();
+    let bytes = fs::read(&source).unwrap();
+    db.compact_now().unwrap();
+    assert!(!source.exists());
+    assert_eq!(db.get("small3").unwrap(), None);
+    // 模拟wal落盘后、写入原文件与删除原文件前崩溃
+    drop(db);
+    fs::write(&source, &bytes).unwrap();
+    remove_indexfiles(dir.path());
+
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    for i in 0..8 {
+        let expected = match i {
+            3 => None,
+            i => Some(format!("value{}", i).into_bytes()),
+        };
+        assert_eq!(
+            db.get(format!("small{}", i)).unwrap(),
+            expected,
+            "small{}",
+            i
+        );
+    }
+    assert_eq!(db.get("filler").unwrap(), None);
+}
+
+#[test]
+fn compaction_merges_sparse_files_into_one() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        mmap_sealed: true,
+        cache_capacity: 0,
+        ..Options::default()
+    };
+    let count = 100;
+    let kept = |i: u32| i.is_multiple_of(10) || i >= 90;
+    {
+        let db = Db::open_with_options(dir.path(), options.clone()).unwrap();
+        for i in 0..count {
+            db.put(format!("key{}", i), value(i)).unwrap();
+        }
+        for i in (0..count).filter(|i| !kept(*i)) {
+            db.delete(format!("key{}", i)).unwrap();
+        }
+        assert_eq!(db.get("key0").unwrap(), Some(value(0)));
+        let before = db.stats();
+        assert!(before.data_files >= 4);
+        assert_eq!(before.file_handles, before.data_files + 1);
+        assert!(before.mapped_files >= 1);
+
+        db.compact_now().unwrap();
+        // 所有已封存文件合并为一个目标文件,另有一个活跃文件
+        let after = db.stats();
+        assert_eq!(after.data_files, 2);
+        assert_eq!(after.file_handles, 3);
+        assert_eq!(after.mapped_files, 0);
+        assert_eq!(datafiles(dir.path()).len(), 2);
+        for i in 0..count {
+            let expected = if kept(i) { Some(value(i)) } else { None };
+            assert_eq!(db.get(format!("key{}", i)).unwrap(), expected, "key{}", i);
+        }
+        db.close().unwrap();
+    }
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    assert_eq!(db.stats().data_files, 2);
+    for i in 0..count {
+        let expected = if kept(i) { Some(value(i)) } else { None };
+        assert_eq!(db.get(format!("key{}", i)).unwrap(), expected, "key{}", i);
+    }
+    db.put("new", "value").unwrap();
+    assert_eq!(db.get("new").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn later_rounds_fill_the_previous_target() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    // 第一轮: 第一个文件只剩少量存活记录,压缩后目标文件大部分空闲
+    for i in 0..COUNT {
+        db.put(format!("a{}", i), value(i)).unwrap();
+    }
+    for i in 0..25 {
+        db.delete(format!("a{}", i)).unwrap();
+    }
+    db.compact_now().unwrap();
+    let first = datafiles(dir.path());
+
+    // 第二轮: 写满并封存活跃文件,再删除其中一半新写入的key留下空洞
+    for i in 0..COUNT {
+        db.put(format!("b{}", i), value(i)).unwrap();
+    }
+    for i in (0..COUNT).filter(|i| i % 2 == 0) {
+        db.delete(format!("b{}", i)).unwrap();
+    }
+    let before = datafiles(dir.path());
+    assert!(before.len() > first.len());
+    db.compact_now().unwrap();
+    // 存活记录写入上一轮目标文件的空闲空间,没有新建目标文件
+    let after = datafiles(dir.path());
+    assert_eq!(after.len(), before.len() - 1);
+    assert!(after.iter().all(|path| before.contains(path)));
+    for i in 0..COUNT {
+        let expected = if i < 25 { None } else { Some(value(i)) };
+        assert_eq!(db.get(format!("a{}", i)).unwrap(), expected, "a{}", i);
+        let expected = if i % 2 == 0 { None } else { Some(value(i)) };
+        assert_eq!(db.get(format!("b{}", i)).unwrap(), expected, "b{}", i);
+    }
+}
+
+#[test]
+fn compact_now_runs_a_pass() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    for i in 0..100 {
+        db.put(format!("key{}", i), format!("value{}", i)).unwrap();
+    }
+    db.compact_now().unwrap();
+    db.compact_now().unwrap();
+    assert_eq!(db.stats().compactions, 2);
+    for i in 0..100 {
+        assert_eq!(
+            db.get(format!("key{}", i)).unwrap(),
+            Some(format!("value{}", i).into_bytes())
+        );
+    }
+}
+
+#[test]
+fn background_compaction_is_off_by_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        compaction_interval: Duration::from_millis(1),
+        ..Options::default()
+    };
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(db.stats().compactions, 0);
+}
+
+#[test]
+fn background_compaction_wakes_on_timer() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = background(Duration::from_millis(20), 1.0);
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    db.put("key", "value").unwrap();
+    assert!(wait_for_compactions(&db, 2));
+    assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+    db.close().unwrap();
+}
+
+#[test]
+fn background_compaction_wakes_on_threshold() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = background(Duration::from_secs(3600), 0.0);
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    for i in 0..1000 {
+        db.put(format!("key{}", i), "value").unwrap();
+    }
+    assert!(wait_for_compactions(&db, 1));
+    db.close().unwrap();
+}
+
+#[test]
+fn closing_stops_the_compactor() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = background(Duration::from_secs(3600), 1.0);
+    {
+        let db = Db::open_with_options(dir.path(), options.clone()).unwrap();
+        db.put("key", "value").unwrap();
+        let start = Instant::now();
+        db.close().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+    {
+        // 未调用close时drop同样停止压缩线程
+        let db = Db::open_with_options(dir.path(), options.clone()).unwrap();
+        db.put("other", "value").unwrap();
+    }
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(db.get("other").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn compaction_target_is_not_offered_to_the_policy() {
+    let dir = tempfile::tempdir().unwrap();
+    let policy = Arc::new(SelectAll::default());
+    let db = Db::open_with_options(dir.path(), with_policy(policy.clone())).unwrap();
+    fill_with_dead_space(&db);
+    db.compact_now().unwrap();
+    // 第一轮压缩所有已封存文件,剩下目标文件与活跃文件
+    assert_eq!(datafiles(dir.path()).len(), 2);
+    db.compact_now().unwrap();
+    let offered = policy.offered.lock().unwrap().clone();
+    assert_eq!(offered.len(), 2);
+    assert!(!offered[0].is_empty());
+    // 目标文件仍在被填充,不再被提供给策略
+    assert!(offered[1].is_empty());
+    assert_eq!(datafiles(dir.path()).len(), 2);
+    for i in 0..COUNT {
+        assert_eq!(
+            db.get(format!("key{}", i)).unwrap(),
+            expected(i),
+            "key{}",
+            i
+        );
+    }
+}
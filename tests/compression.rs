@@ -0,0 +1,107 @@
+extern crate koundb;
+extern crate tempfile;
+
+mod common;
+
+use common::{datafiles, datasize};
+use koundb::{Compression, Db, Options, FILE_HEADER_SIZE};
+use std::fs;
+
+// 记录头中codec的偏移
+const CODEC_OFFSET: usize = 4 + 2 + 1;
+
+fn lz4() -> Options {
+    Options {
+        compression: Compression::Lz4,
+        ..Options::default()
+    }
+}
+
+fn document(i: u32) -> Vec<u8> {
+    let mut doc = String::from("[");
+    for j in 0..20 {
+        doc.push_str(&format!(
+            "{{\"id\":{},\"name\":\"user-{}\",\"active\":true,\"tags\":[\"a\",\"b\"]}},",
+            i * 100 + j,
+            j
+        ));
+    }
+    doc.push(']');
+    doc.into_bytes()
+}
+
+// 不可压缩的伪随机数据
+fn noise(len: usize, mut seed: u64) -> Vec<u8> {
+    (0..len)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u8
+        })
+        .collect()
+}
+
+#[test]
+fn compressed_values_round_trip() {
+    let plain = tempfile::tempdir().unwrap();
+    let packed = tempfile::tempdir().unwrap();
+    let plaindb = Db::open(plain.path()).unwrap();
+    let packeddb = Db::open_with_options(packed.path(), lz4()).unwrap();
+    for i in 0..200u32 {
+        plaindb.put(format!("doc{}", i), document(i)).unwrap();
+        packeddb.put(format!("doc{}", i), document(i)).unwrap();
+    }
+    assert!(datasize(packed.path()) * 3 < datasize(plain.path()));
+    for i in 0..200u32 {
+        assert_eq!(
+            packeddb.get(format!("doc{}", i)).unwrap(),
+            Some(document(i))
+        );
+    }
+    let (key, value) = packeddb.prefix("doc19").next().unwrap().unwrap();
+    assert_eq!(key, b"doc19".to_vec());
+    assert_eq!(value, document(19));
+    packeddb.close().unwrap();
+
+    let packeddb = Db::open_with_options(packed.path(), lz4()).unwrap();
+    assert_eq!(packeddb.get("doc7").unwrap(), Some(document(7)));
+}
+
+#[test]
+fn incompressible_value_is_stored_raw() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open_with_options(dir.path(), lz4()).unwrap();
+    let value = noise(500, 0x9e37_79b9_7f4a_7c15);
+    db.put("noise", value.clone()).unwrap();
+
+    let bytes = fs::read(&datafiles(dir.path())[0]).unwrap();
+    assert_eq!(bytes[FILE_HEADER_SIZE as usize + CODEC_OFFSET], 0);
+    assert_eq!(db.get("noise").unwrap(), Some(value));
+
+    db.put("doc", document(1)).unwrap();
+    assert_eq!(db.get("doc").unwrap(), Some(document(1)));
+}
+
+#[test]
+fn compression_is_per_record() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open_with_options(dir.path(), lz4()).unwrap();
+    db.put("packed", document(1)).unwrap();
+    db.close().unwrap();
+
+    // 关闭压缩后旧记录仍可读取,新记录不压缩
+    let db = Db::open(dir.path()).unwrap();
+    db.put("plain", document(2)).unwrap();
+    assert_eq!(db.get("packed").unwrap(), Some(document(1)));
+    assert_eq!(db.get("plain").unwrap(), Some(document(2)));
+    let snapshot = db.snapshot();
+    assert!(db.delete("packed").unwrap());
+    assert_eq!(snapshot.get("packed").unwrap(), Some(document(1)));
+    drop(snapshot);
+    db.close().unwrap();
+
+    let db = Db::open_with_options(dir.path(), lz4()).unwrap();
+    assert_eq!(db.get("packed").unwrap(), None);
+    assert_eq!(db.get("plain").unwrap(), Some(document(2)));
+}
@@ -0,0 +1,144 @@
+extern crate koundb;
+extern crate tempfile;
+
+use koundb::{Db, Error, Iter, Snapshot, Transaction};
+use std::sync::Arc;
+use std::thread;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn handles_are_send_and_sync() {
+    assert_send_sync::<Db>();
+    assert_send_sync::<Snapshot>();
+    assert_send_sync::<Iter>();
+    assert_send_sync::<Transaction>();
+}
+
+#[test]
+fn parallel_readers_and_writers() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Arc::new(Db::open(dir.path()).unwrap());
+    for i in 0..100 {
+        db.put(format!("shared{:03}", i), "initial").unwrap();
+    }
+    let mut handles = Vec::new();
+    for t in 0..4 {
+        let db = db.clone();
+        handles.push(thread::spawn(move || {
+            for i in 0..200 {
+                db.put(format!("writer{}-{:03}", t, i), format!("{}", i))
+                    .unwrap();
+            }
+        }));
+    }
+    for _ in 0..4 {
+        let db = db.clone();
+        handles.push(thread::spawn(move || {
+            for _ in 0..5 {
+                for i in 0..100 {
+                    let value = db.get(format!("shared{:03}", i)).unwrap();
+                    assert_eq!(value, Some(b"initial".to_vec()));
+                }
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    for t in 0..4 {
+        assert_eq!(db.prefix(format!("writer{}-", t)).count(), 200);
+    }
+    Arc::try_unwrap(db).unwrap().close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.iter().count(), 900);
+}
+
+#[test]
+fn iterator_is_not_affected_by_concurrent_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Arc::new(Db::open(dir.path()).unwrap());
+    for i in 0..50 {
+        db.put(format!("key{:02}", i), format!("value{:02}", i))
+            .unwrap();
+    }
+    let iter = db.iter();
+    let writer = {
+        let db = db.clone();
+        thread::spawn(move || {
+            // 删除并覆盖所有key,被释放的空间在迭代器存活期间不会被复用
+            for i in 0..50 {
+                db.delete(format!("key{:02}", i)).unwrap();
+            }
+            for i in 0..50 {
+                db.put(format!("new{:02}", i), vec![0xffu8; 12]).unwrap();
+            }
+        })
+    };
+    writer.join().unwrap();
+    let items: Vec<(Vec<u8>, Vec<u8>)> = iter.map(|item| item.unwrap()).collect();
+    assert_eq!(items.len(), 50);
+    for (i, (key, value)) in items.into_iter().enumerate() {
+        assert_eq!(key, format!("key{:02}", i).into_bytes());
+        assert_eq!(value, format!("value{:02}", i).into_bytes());
+    }
+    assert_eq!(db.iter().count(), 50);
+    assert_eq!(db.prefix("key").count(), 0);
+}
+
+#[test]
+fn snapshot_reads_while_key_is_overwritten() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Arc::new(Db::open(dir.path()).unwrap());
+    db.put("key", "original").unwrap();
+    let snapshot = db.snapshot();
+    let reader = thread::spawn(move || {
+        for _ in 0..500 {
+            assert_eq!(snapshot.get("key").unwrap(), Some(b"original".to_vec()));
+        }
+    });
+    for i in 0..500 {
+        db.put("key", format!("value{}", i)).unwrap();
+    }
+    reader.join().unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"value499".to_vec()));
+}
+
+#[test]
+fn concurrent_transactions_serialize() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Arc::new(Db::open(dir.path()).unwrap());
+    db.put("counter", 0u64.to_le_bytes().to_vec()).unwrap();
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let db = db.clone();
+            thread::spawn(move || {
+                for _ in 0..25 {
+                    // 冲突时重试,直到成功提交
+                    loop {
+                        let result = db.transaction(|txn| {
+                            let value = txn.get("counter")?.unwrap();
+                            let mut bytes = [0u8; 8];
+                            bytes.copy_from_slice(&value);
+                            let counter = u64::from_le_bytes(bytes) + 1;
+                            txn.put("counter", counter.to_le_bytes().to_vec())
+                        });
+                        match result {
+                            Ok(()) => break,
+                            Err(Error::Conflict(_)) => continue,
+                            Err(err) => panic!("{}", err),
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let value = db.get("counter").unwrap().unwrap();
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&value);
+    assert_eq!(u64::from_le_bytes(bytes), 100);
+}
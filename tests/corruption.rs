@@ -0,0 +1,178 @@
+extern crate koundb;
+extern crate tempfile;
+
+mod common;
+
+use common::{datafile, fileid};
+use koundb::{Db, Error, Options, FILE_HEADER_SIZE, RECORD_HEADER_SIZE};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// 记录头之后是key与value
+const BODY: u64 = RECORD_HEADER_SIZE as u64;
+
+// 不缓存value,读取总是经过.data文件
+fn open_uncached(dir: &Path) -> Db {
+    let options = Options {
+        cache_capacity: 0,
+        ..Options::default()
+    };
+    Db::open_with_options(dir, options).unwrap()
+}
+
+// 翻转文件中offset处字节的最低位
+fn flip_bit(path: &Path, offset: u64) {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .unwrap();
+    let mut byte = [0u8; 1];
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.read_exact(&mut byte).unwrap();
+    byte[0] ^= 1;
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(&byte).unwrap();
+}
+
+#[test]
+fn get_detects_bit_flip() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open_uncached(dir.path());
+    db.put("first", "value").unwrap();
+    db.put("second", "value").unwrap();
+
+    // 第一条记录位于偏移0处,翻转其value中的一位
+    let path = datafile(dir.path());
+    let len = fs::metadata(&path).unwrap().len();
+    flip_bit(&path, FILE_HEADER_SIZE + BODY + 5 + 2);
+    assert_eq!(fs::metadata(&path).unwrap().len(), len);
+
+    match db.get("first") {
+        Err(Error::Corruption {
+            fileid: id,
+            offset: 0,
+        }) => assert_eq!(id, fileid(&path)),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(db.get("second").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn get_detects_header_corruption() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open_uncached(dir.path());
+    db.put("key", "value").unwrap();
+
+    // 翻转记录头中的time
+    let path = datafile(dir.path());
+    flip_bit(&path, FILE_HEADER_SIZE + 4 + 2 + 1 + 1 + 4);
+    match db.get("key") {
+        Err(Error::Corruption { offset: 0, .. }) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn get_detects_truncated_record() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open_uncached(dir.path());
+    db.put("key", vec![1u8; 100]).unwrap();
+
+    // valuesize变大后记录超出文件末尾
+    let path = datafile(dir.path());
+    flip_bit(&path, FILE_HEADER_SIZE + 4 + 2 + 1 + 1 + 2);
+    match db.get("key") {
+        Err(Error::Corruption { offset: 0, .. }) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+// 写入两条记录后关闭,删除.index文件并翻转第一条记录key中的一位
+// 返回.data文件的路径
+fn corrupt_first_record(dir: &Path) -> PathBuf {
+    let db = Db::open(dir).unwrap();
+    db.put("first", "value").unwrap();
+    db.put("second", "value").unwrap();
+    db.close().unwrap();
+
+    // 删除.index文件,打开时扫描.data文件
+    let path = datafile(dir);
+    fs::remove_file(path.with_extension("index")).unwrap();
+    flip_bit(&path, FILE_HEADER_SIZE + BODY + 1);
+    path
+}
+
+fn open_repair(dir: &Path) -> Db {
+    let options = Options {
+        repair: true,
+        ..Options::default()
+    };
+    Db::open_with_options(dir, options).unwrap()
+}
+
+#[test]
+fn recovery_reports_corrupted_record() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = corrupt_first_record(dir.path());
+    match Db::open(dir.path()) {
+        Err(Error::Corruption {
+            fileid: id,
+            offset: 0,
+        }) => assert_eq!(id, fileid(&path)),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn repair_keeps_corrupted_record_allocated() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = corrupt_first_record(dir.path());
+    let corrupted = fs::read(&path).unwrap()[..FILE_HEADER_SIZE as usize + 48].to_vec();
+
+    let db = open_repair(dir.path());
+    assert_eq!(db.stats().corrupt_records, 1);
+    assert_eq!(db.get("first").unwrap(), None);
+    assert_eq!(db.get("second").unwrap(), Some(b"value".to_vec()));
+    // 损坏记录的空间不被重新分配
+    db.put("third", "value").unwrap();
+    assert_eq!(db.get("second").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(db.get("third").unwrap(), Some(b"value".to_vec()));
+    db.close().unwrap();
+    assert!(fs::read(&path).unwrap().starts_with(&corrupted));
+
+    // 损坏记录所在的文件没有.index文件,之后的打开仍会发现它
+    assert!(Db::open(dir.path()).is_err());
+    let db = open_repair(dir.path());
+    assert_eq!(db.stats().corrupt_records, 1);
+    assert_eq!(db.get("third").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn corrupted_records_can_be_overwritten_and_deleted() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open_uncached(dir.path());
+    db.put("first", "value").unwrap();
+    db.put("second", "value").unwrap();
+    db.put("third", "value").unwrap();
+
+    // 三条记录各占48字节,翻转前两条记录value中的一位
+    let path = datafile(dir.path());
+    flip_bit(&path, FILE_HEADER_SIZE + BODY + 5 + 2);
+    flip_bit(&path, FILE_HEADER_SIZE + 48 + BODY + 6 + 2);
+    assert!(db.get("first").is_err());
+    assert!(db.get("second").is_err());
+
+    // 覆盖与删除不读取原记录,损坏的key可以被修复
+    db.put("first", "repaired").unwrap();
+    assert!(db.delete("second").unwrap());
+    assert_eq!(db.get("first").unwrap(), Some(b"repaired".to_vec()));
+    assert_eq!(db.get("second").unwrap(), None);
+    db.close().unwrap();
+
+    let db = open_uncached(dir.path());
+    assert_eq!(db.get("first").unwrap(), Some(b"repaired".to_vec()));
+    assert_eq!(db.get("second").unwrap(), None);
+    assert_eq!(db.get("third").unwrap(), Some(b"value".to_vec()));
+}
@@ -0,0 +1,117 @@
+extern crate koundb;
+extern crate tempfile;
+
+use koundb::{Db, Error};
+
+#[test]
+fn put_get_delete() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+
+    assert_eq!(db.get("missing").unwrap(), None);
+    db.put("hello", "world").unwrap();
+    db.put("foo", "bar").unwrap();
+    assert_eq!(db.get("hello").unwrap(), Some(b"world".to_vec()));
+    assert_eq!(db.get("foo").unwrap(), Some(b"bar".to_vec()));
+
+    assert!(db.delete("hello").unwrap());
+    assert!(!db.delete("hello").unwrap());
+    assert_eq!(db.get("hello").unwrap(), None);
+    assert_eq!(db.get("foo").unwrap(), Some(b"bar".to_vec()));
+    db.close().unwrap();
+}
+
+#[test]
+fn overwrite_keeps_latest_value() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+
+    for i in 0..100u32 {
+        db.put("counter", i.to_string()).unwrap();
+    }
+    assert_eq!(db.get("counter").unwrap(), Some(b"99".to_vec()));
+
+    // 不同长度的value复用已释放的空间
+    db.put("counter", vec![7u8; 1000]).unwrap();
+    db.put("other", vec![1u8; 10]).unwrap();
+    assert_eq!(db.get("counter").unwrap(), Some(vec![7u8; 1000]));
+    assert_eq!(db.get("other").unwrap(), Some(vec![1u8; 10]));
+    db.close().unwrap();
+}
+
+#[test]
+fn many_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+
+    for i in 0..1000u32 {
+        db.put(format!("key{}", i), format!("value{}", i)).unwrap();
+    }
+    for i in (0..1000u32).filter(|i| i % 3 == 0) {
+        assert!(db.delete(format!("key{}", i)).unwrap());
+    }
+    for i in 0..1000u32 {
+        let expected = if i % 3 == 0 {
+            None
+        } else {
+            Some(format!("value{}", i).into_bytes())
+        };
+        assert_eq!(db.get(format!("key{}", i)).unwrap(), expected);
+    }
+    db.close().unwrap();
+}
+
+#[test]
+fn invalid_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+
+    match db.put("", "value") {
+        Err(Error::InvalidKey(..)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match db.get(vec![0u8; 1 << 16]) {
+        Err(Error::InvalidKey(..)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn open_creates_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nested").join("db");
+    let db = Db::open(&path).unwrap();
+    db.put("key", "value").unwrap();
+    assert!(path.is_dir());
+    db.close().unwrap();
+}
+
+#[test]
+fn oversized_put_keeps_existing_value() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("k", "old").unwrap();
+    let datafiles = || {
+        std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|entry| {
+                let path = entry.as_ref().unwrap().path();
+                path.extension().is_some_and(|ext| ext == "data")
+            })
+            .count()
+    };
+    let before = datafiles();
+    for _ in 0..3 {
+        match db.put("k", vec![0u8; 33 << 20]) {
+            Err(Error::Allocatefail(_)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    assert_eq!(db.get("k").unwrap(), Some(b"old".to_vec()));
+    // 失败的写入不会留下空文件
+    assert_eq!(datafiles(), before);
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("k").unwrap(), Some(b"old".to_vec()));
+}
@@ -0,0 +1,147 @@
+extern crate koundb;
+extern crate tempfile;
+
+mod common;
+
+use common::files_with_extension;
+use koundb::{Compression, Db, Error, Options};
+use std::fs;
+use std::path::Path;
+
+fn with_key(key: u8) -> Options {
+    Options {
+        encryption_key: Some([key; 32]),
+        ..Options::default()
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+// 目录下任意文件包含needle
+fn any_file_contains(dir: &Path, needle: &[u8]) -> bool {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| fs::read(entry.unwrap().path()).unwrap())
+        .any(|bytes| contains(&bytes, needle))
+}
+
+#[test]
+fn files_contain_no_plaintext() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = Db::open_with_options(dir.path(), with_key(1)).unwrap();
+        db.put("secret-key", "secret-value").unwrap();
+        db.put("other-key", "other-value").unwrap();
+        db.delete("other-key").unwrap();
+    }
+    // 未关闭时wal中同样只有密文
+    assert!(!any_file_contains(dir.path(), b"secret"));
+    assert!(!any_file_contains(dir.path(), b"other"));
+
+    let db = Db::open_with_options(dir.path(), with_key(1)).unwrap();
+    assert_eq!(
+        db.get("secret-key").unwrap(),
+        Some(b"secret-value".to_vec())
+    );
+    assert_eq!(db.get("other-key").unwrap(), None);
+    db.close().unwrap();
+    assert!(!any_file_contains(dir.path(), b"secret"));
+
+    // 从加密的.index文件恢复
+    let db = Db::open_with_options(dir.path(), with_key(1)).unwrap();
+    assert_eq!(
+        db.get("secret-key").unwrap(),
+        Some(b"secret-value".to_vec())
+    );
+}
+
+#[test]
+fn wrong_or_missing_key_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open_with_options(dir.path(), with_key(1)).unwrap();
+    db.put("key", "value").unwrap();
+    db.close().unwrap();
+
+    match Db::open_with_options(dir.path(), with_key(2)) {
+        Err(Error::Decryption { .. }) => {}
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+    match Db::open(dir.path()) {
+        Err(ref err @ Error::Decryption { .. }) => {
+            assert!(err.to_string().contains("encryption key"))
+        }
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    // 失败的打开没有破坏数据
+    let db = Db::open_with_options(dir.path(), with_key(1)).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn wrong_key_keeps_index_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open_with_options(dir.path(), with_key(1)).unwrap();
+    db.put("key", "value").unwrap();
+    db.close().unwrap();
+    assert_eq!(files_with_extension(dir.path(), "index").len(), 1);
+
+    // 密钥错误时.index文件无法解密,但它并没有损坏
+    for _ in 0..2 {
+        match Db::open_with_options(dir.path(), with_key(2)) {
+            Err(Error::Decryption { .. }) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert!(Db::open(dir.path()).is_err());
+        assert_eq!(files_with_extension(dir.path(), "index").len(), 1);
+    }
+
+    let db = Db::open_with_options(dir.path(), with_key(1)).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn encryption_with_compression() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        compression: Compression::Lz4,
+        ..with_key(3)
+    };
+    let value = "{\"balance\":100,\"currency\":\"EUR\"}".repeat(50);
+    let db = Db::open_with_options(dir.path(), options.clone()).unwrap();
+    db.put("account", value.clone()).unwrap();
+    let snapshot = db.snapshot();
+    db.put("account", "closed").unwrap();
+    assert_eq!(
+        snapshot.get("account").unwrap(),
+        Some(value.clone().into_bytes())
+    );
+    drop(snapshot);
+    db.close().unwrap();
+    assert!(!any_file_contains(dir.path(), b"closed"));
+
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    assert_eq!(db.get("account").unwrap(), Some(b"closed".to_vec()));
+}
+
+#[test]
+fn plaintext_records_stay_readable_after_enabling_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("old", "plain").unwrap();
+    db.close().unwrap();
+
+    let db = Db::open_with_options(dir.path(), with_key(4)).unwrap();
+    assert_eq!(db.get("old").unwrap(), Some(b"plain".to_vec()));
+    db.put("new", "hidden").unwrap();
+    db.close().unwrap();
+    assert!(!any_file_contains(dir.path(), b"hidden"));
+
+    let db = Db::open_with_options(dir.path(), with_key(4)).unwrap();
+    assert_eq!(db.get("old").unwrap(), Some(b"plain".to_vec()));
+    assert_eq!(db.get("new").unwrap(), Some(b"hidden".to_vec()));
+}
@@ -0,0 +1,110 @@
+extern crate crc32c;
+extern crate koundb;
+extern crate tempfile;
+
+mod common;
+
+use common::{datafile, fileid, files_with_extension};
+use crc32c::crc32c;
+use koundb::{Db, Error, FILE_HEADER_SIZE, FORMAT_VERSION};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+fn read_header(path: &Path) -> [u8; FILE_HEADER_SIZE as usize] {
+    let mut header = [0u8; FILE_HEADER_SIZE as usize];
+    File::open(path).unwrap().read_exact(&mut header).unwrap();
+    header
+}
+
+fn write_at(path: &Path, offset: u64, bytes: &[u8]) {
+    let mut file = OpenOptions::new().write(true).open(path).unwrap();
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(bytes).unwrap();
+}
+
+#[test]
+fn files_start_with_header() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", "value").unwrap();
+    db.close().unwrap();
+
+    let data = datafile(dir.path());
+    let id = fileid(&data);
+    let header = read_header(&data);
+    assert_eq!(&header[0..6], b"KOUNDB");
+    // kind, version, fileid
+    assert_eq!(header[6], 1);
+    assert_eq!(header[7], FORMAT_VERSION);
+    assert_eq!(&header[8..16], &id.to_le_bytes());
+
+    let index = files_with_extension(dir.path(), "index").remove(0);
+    let header = read_header(&index);
+    assert_eq!(&header[0..6], b"KOUNDB");
+    assert_eq!(header[6], 2);
+    assert_eq!(&header[8..16], &id.to_le_bytes());
+}
+
+#[test]
+fn bad_magic_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", "value").unwrap();
+    db.close().unwrap();
+
+    let data = datafile(dir.path());
+    write_at(&data, 0, b"NOTKDB");
+    match Db::open(dir.path()) {
+        Err(Error::InvalidFormat(..)) => {}
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn unknown_version_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", "value").unwrap();
+    db.close().unwrap();
+
+    // 修改版本号并重新计算文件头校验和
+    let data = datafile(dir.path());
+    let mut header = read_header(&data);
+    header[7] += 1;
+    let crc = crc32c(&header[0..24]);
+    header[24..28].copy_from_slice(&crc.to_le_bytes());
+    write_at(&data, 0, &header);
+    match Db::open(dir.path()) {
+        Err(Error::InvalidFormat(ref msg)) => assert!(msg.contains("version")),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn bad_indexfile_header_falls_back_to_scan() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", "value").unwrap();
+    db.close().unwrap();
+
+    let index = files_with_extension(dir.path(), "index").remove(0);
+    write_at(&index, 0, b"XXXXXX");
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn empty_datafile_gets_header() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", "value").unwrap();
+    db.close().unwrap();
+
+    // 新建文件后写文件头前崩溃
+    File::create(dir.path().join("1.data")).unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+    db.close().unwrap();
+    assert_eq!(read_header(&dir.path().join("1.data"))[0..6], *b"KOUNDB");
+}
@@ -0,0 +1,120 @@
+extern crate koundb;
+extern crate tempfile;
+
+mod common;
+
+use common::files_with_extension;
+use koundb::Db;
+use std::fs::{self, OpenOptions};
+
+fn fill(db: &Db, count: u32) {
+    for i in 0..count {
+        db.put(format!("key{}", i), format!("value{}", i)).unwrap();
+    }
+    for i in (0..count).filter(|i| i % 4 == 0) {
+        db.delete(format!("key{}", i)).unwrap();
+    }
+}
+
+fn check(db: &Db, count: u32) {
+    for i in 0..count {
+        let expected = if i % 4 == 0 {
+            None
+        } else {
+            Some(format!("value{}", i).into_bytes())
+        };
+        assert_eq!(db.get(format!("key{}", i)).unwrap(), expected);
+    }
+}
+
+#[test]
+fn close_writes_indexfiles() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    fill(&db, 300);
+    db.close().unwrap();
+    assert_eq!(
+        files_with_extension(dir.path(), "index").len(),
+        files_with_extension(dir.path(), "data").len()
+    );
+
+    let db = Db::open(dir.path()).unwrap();
+    check(&db, 300);
+    // 从.index恢复的freelist不能覆盖存活记录
+    for i in 300..400u32 {
+        db.put(format!("key{}", i), format!("value{}", i)).unwrap();
+    }
+    check(&db, 300);
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    check(&db, 300);
+    for i in 300..400u32 {
+        assert_eq!(
+            db.get(format!("key{}", i)).unwrap(),
+            Some(format!("value{}", i).into_bytes())
+        );
+    }
+}
+
+#[test]
+fn write_invalidates_indexfile() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    fill(&db, 100);
+    db.close().unwrap();
+    assert_eq!(files_with_extension(dir.path(), "index").len(), 1);
+
+    {
+        let db = Db::open(dir.path()).unwrap();
+        db.put("key1", "changed").unwrap();
+        db.delete("key2").unwrap();
+        // 不调用close,.index文件已被删除
+        assert!(files_with_extension(dir.path(), "index").is_empty());
+    }
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key1").unwrap(), Some(b"changed".to_vec()));
+    assert_eq!(db.get("key2").unwrap(), None);
+    assert_eq!(db.get("key3").unwrap(), Some(b"value3".to_vec()));
+}
+
+#[test]
+fn truncated_indexfile_falls_back_to_scan() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    fill(&db, 100);
+    db.close().unwrap();
+
+    let path = files_with_extension(dir.path(), "index").pop().unwrap();
+    let len = fs::metadata(&path).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len / 2).unwrap();
+    drop(file);
+
+    let db = Db::open(dir.path()).unwrap();
+    check(&db, 100);
+    assert!(files_with_extension(dir.path(), "index").is_empty());
+}
+
+#[test]
+fn stale_indexfile_falls_back_to_scan() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    fill(&db, 100);
+    db.close().unwrap();
+
+    // .data文件长度与.index中记录的不一致
+    let path = files_with_extension(dir.path(), "data").pop().unwrap();
+    let len = fs::metadata(&path).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len.div_ceil(16) * 16 + 64).unwrap();
+    drop(file);
+
+    let db = Db::open(dir.path()).unwrap();
+    check(&db, 100);
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    check(&db, 100);
+}
@@ -0,0 +1,85 @@
+extern crate koundb;
+extern crate tempfile;
+
+use koundb::{Db, Error};
+
+fn keys<I>(iter: I) -> Vec<String>
+where
+    I: Iterator<Item = Result<(Vec<u8>, Vec<u8>), Error>>,
+{
+    iter.map(|item| String::from_utf8(item.unwrap().0).unwrap())
+        .collect()
+}
+
+fn open_filled(dir: &tempfile::TempDir) -> Db {
+    let db = Db::open(dir.path()).unwrap();
+    for key in &["b", "a", "ab", "abc", "b\u{7f}", "c", "ac"] {
+        db.put(*key, format!("value-{}", key)).unwrap();
+    }
+    db
+}
+
+#[test]
+fn iter_is_ordered() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open_filled(&dir);
+    db.delete("ac").unwrap();
+    db.put("b", "changed").unwrap();
+
+    let items: Vec<(Vec<u8>, Vec<u8>)> = db.iter().map(|item| item.unwrap()).collect();
+    assert_eq!(items.len(), 6);
+    assert_eq!(items[0], (b"a".to_vec(), b"value-a".to_vec()));
+    assert_eq!(items[3], (b"b".to_vec(), b"changed".to_vec()));
+    assert_eq!(keys(db.iter()), vec!["a", "ab", "abc", "b", "b\u{7f}", "c"]);
+    assert_eq!(
+        keys(db.iter().rev()),
+        vec!["c", "b\u{7f}", "b", "abc", "ab", "a"]
+    );
+}
+
+#[test]
+fn range_bounds() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open_filled(&dir);
+
+    assert_eq!(keys(db.range("ab".."b")), vec!["ab", "abc", "ac"]);
+    assert_eq!(keys(db.range("ab"..="b")), vec!["ab", "abc", "ac", "b"]);
+    assert_eq!(keys(db.range("b\u{7f}"..)), vec!["b\u{7f}", "c"]);
+    assert_eq!(keys(db.range(.."ab")), vec!["a"]);
+    assert_eq!(keys(db.range("ab".."b").rev()), vec!["ac", "abc", "ab"]);
+    // 空区间和反向区间
+    assert!(keys(db.range("b".."b")).is_empty());
+    assert!(keys(db.range("c".."a")).is_empty());
+}
+
+#[test]
+fn prefix_scan() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open_filled(&dir);
+    db.put(vec![b'a', 0xff], "x").unwrap();
+    db.put(vec![0xff, 0xff], "y").unwrap();
+    db.put(vec![0xff, 0xff, 1], "z").unwrap();
+
+    assert_eq!(keys(db.prefix("ab")), vec!["ab", "abc"]);
+    assert_eq!(keys(db.prefix("b")), vec!["b", "b\u{7f}"]);
+    assert_eq!(db.prefix("a").rev().count(), 5);
+    assert!(keys(db.prefix("d")).is_empty());
+    let ffkeys: Vec<Vec<u8>> = db
+        .prefix(vec![0xff, 0xff])
+        .map(|item| item.unwrap().0)
+        .collect();
+    assert_eq!(ffkeys, vec![vec![0xff, 0xff], vec![0xff, 0xff, 1]]);
+}
+
+#[test]
+fn iter_after_reopen_and_mixed_direction() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open_filled(&dir);
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    let mut iter = db.iter();
+    assert_eq!(iter.next().unwrap().unwrap().0, b"a".to_vec());
+    assert_eq!(iter.next_back().unwrap().unwrap().0, b"c".to_vec());
+    assert_eq!(keys(iter), vec!["ab", "abc", "ac", "b", "b\u{7f}"]);
+}
@@ -0,0 +1,143 @@
+extern crate koundb;
+extern crate tempfile;
+
+mod common;
+
+use common::datafiles;
+use koundb::{Compression, Db, Options};
+
+// 足以让第一个.data文件封存的value大小与数量
+const VALUE_SIZE: usize = 1 << 20;
+const COUNT: u32 = 40;
+
+fn options() -> Options {
+    Options {
+        cache_capacity: 0,
+        mmap_sealed: true,
+        ..Options::default()
+    }
+}
+
+// 不可压缩的value
+fn value(i: u32) -> Vec<u8> {
+    let mut state = 0x9e37_79b9_7f4a_7c15u64 ^ u64::from(i + 1);
+    (0..VALUE_SIZE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+fn fill(db: &Db) {
+    for i in 0..COUNT {
+        db.put(format!("key{}", i), value(i)).unwrap();
+    }
+}
+
+#[test]
+fn sealed_files_are_read_through_the_map() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open_with_options(dir.path(), options()).unwrap();
+    fill(&db);
+    assert!(datafiles(dir.path()).len() > 1);
+    for i in 0..COUNT {
+        let expected = value(i);
+        assert_eq!(
+            db.get(format!("key{}", i)).unwrap().as_ref(),
+            Some(&expected)
+        );
+        let same = db
+            .get_with(format!("key{}", i), |bytes| bytes == &expected[..])
+            .unwrap();
+        assert_eq!(same, Some(true));
+    }
+    // 已封存文件在读取时建立映射
+    assert!(db.stats().mapped_files >= 1);
+    assert_eq!(db.get_with("missing", |bytes| bytes.len()).unwrap(), None);
+}
+
+// 关闭后重新打开,依次用get、get_with、get读取已封存文件中的key0,返回缓存命中与未命中次数
+fn read_sealed_key(mmap_sealed: bool) -> (u64, u64) {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        cache_capacity: 64 << 20,
+        mmap_sealed,
+        ..options()
+    };
+    {
+        let db = Db::open_with_options(dir.path(), options.clone()).unwrap();
+        fill(&db);
+        db.close().unwrap();
+    }
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    let expected = value(0);
+    assert_eq!(db.get("key0").unwrap(), Some(expected.clone()));
+    let same = db.get_with("key0", |bytes| bytes == &expected[..]).unwrap();
+    assert_eq!(same, Some(true));
+    assert_eq!(db.get("key0").unwrap(), Some(expected));
+    assert_eq!(db.stats().mapped_files >= 1, mmap_sealed);
+    let stats = db.stats();
+    (stats.cache_hits, stats.cache_misses)
+}
+
+#[test]
+fn mapped_reads_do_not_fill_the_cache() {
+    // 从文件读取时第一次读取填入缓存
+    assert_eq!(read_sealed_key(false), (2, 1));
+    // 从映射读取时get与get_with都不填入缓存,每次都直接读取映射
+    assert_eq!(read_sealed_key(true), (0, 3));
+}
+
+#[test]
+fn writes_to_sealed_files_are_visible() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = Db::open_with_options(dir.path(), options()).unwrap();
+        fill(&db);
+        assert_eq!(db.get("key0").unwrap(), Some(value(0)));
+        let snapshot = db.snapshot();
+        db.delete("key0").unwrap();
+        db.put("key1", "small").unwrap();
+        assert_eq!(db.get("key0").unwrap(), None);
+        assert_eq!(db.get("key1").unwrap(), Some(b"small".to_vec()));
+        // 快照仍能读到被原地改写为删除记录的value
+        assert_eq!(snapshot.get("key0").unwrap(), Some(value(0)));
+        let keys: Vec<Vec<u8>> = db.iter().map(|item| item.unwrap().0).collect();
+        assert_eq!(keys.len(), COUNT as usize - 1);
+        db.close().unwrap();
+    }
+    let db = Db::open_with_options(dir.path(), options()).unwrap();
+    assert_eq!(db.get("key0").unwrap(), None);
+    assert_eq!(db.get("key1").unwrap(), Some(b"small".to_vec()));
+    for i in 2..COUNT {
+        assert_eq!(db.get(format!("key{}", i)).unwrap(), Some(value(i)));
+    }
+}
+
+#[test]
+fn compressed_and_encrypted_records_are_decoded_from_the_map() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        compression: Compression::Lz4,
+        encryption_key: Some([3; 32]),
+        ..options()
+    };
+    let db = Db::open_with_options(dir.path(), options.clone()).unwrap();
+    db.put("compressible", vec![b'a'; 4096]).unwrap();
+    fill(&db);
+    assert!(datafiles(dir.path()).len() > 1);
+    assert_eq!(db.get("compressible").unwrap(), Some(vec![b'a'; 4096]));
+    assert_eq!(
+        db.get_with("compressible", |bytes| bytes.len()).unwrap(),
+        Some(4096)
+    );
+    assert_eq!(db.get("key0").unwrap(), Some(value(0)));
+    db.close().unwrap();
+
+    let db = Db::open_with_options(dir.path(), options).unwrap();
+    assert_eq!(db.get("compressible").unwrap(), Some(vec![b'a'; 4096]));
+    assert_eq!(db.get("key0").unwrap(), Some(value(0)));
+}
@@ -0,0 +1,105 @@
+extern crate koundb;
+extern crate tempfile;
+
+mod common;
+
+use common::datafiles;
+use koundb::Db;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+#[test]
+fn reopen_after_close() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    for i in 0..200u32 {
+        db.put(format!("key{}", i), format!("value{}", i)).unwrap();
+    }
+    db.put("key7", "overwritten").unwrap();
+    db.delete("key8").unwrap();
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key0").unwrap(), Some(b"value0".to_vec()));
+    assert_eq!(db.get("key7").unwrap(), Some(b"overwritten".to_vec()));
+    assert_eq!(db.get("key8").unwrap(), None);
+    assert_eq!(db.get("key199").unwrap(), Some(b"value199".to_vec()));
+    db.close().unwrap();
+}
+
+#[test]
+fn reopen_without_close() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = Db::open(dir.path()).unwrap();
+        db.put("a", "1").unwrap();
+        db.put("b", "2").unwrap();
+        db.put("a", "3").unwrap();
+        db.delete("b").unwrap();
+        // 不调用close,模拟进程退出
+    }
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("a").unwrap(), Some(b"3".to_vec()));
+    assert_eq!(db.get("b").unwrap(), None);
+}
+
+#[test]
+fn reopened_freelist_keeps_live_records() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    for i in 0..50u32 {
+        db.put(format!("key{}", i), vec![i as u8; 100]).unwrap();
+    }
+    for i in (0..50u32).filter(|i| i % 2 == 0) {
+        db.delete(format!("key{}", i)).unwrap();
+    }
+    db.close().unwrap();
+
+    // 新写入只能复用已删除记录的空间
+    let db = Db::open(dir.path()).unwrap();
+    for i in 50..100u32 {
+        db.put(format!("key{}", i), vec![i as u8; 60]).unwrap();
+    }
+    for i in 0..100u32 {
+        let expected = if i < 50 && i % 2 == 0 {
+            None
+        } else if i < 50 {
+            Some(vec![i as u8; 100])
+        } else {
+            Some(vec![i as u8; 60])
+        };
+        assert_eq!(db.get(format!("key{}", i)).unwrap(), expected);
+    }
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key99").unwrap(), Some(vec![99u8; 60]));
+    assert_eq!(db.get("key49").unwrap(), Some(vec![49u8; 100]));
+    assert_eq!(db.get("key48").unwrap(), None);
+}
+
+#[test]
+fn torn_tail_is_ignored() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", "value").unwrap();
+    db.close().unwrap();
+
+    // 在文件末尾追加一条只写了一半的记录
+    let path = datafiles(dir.path()).pop().unwrap();
+    let len = fs::metadata(&path).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len.div_ceil(16) * 16).unwrap();
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[3, 0, 100, 0, 0, 0, 1, 2, 3]).unwrap();
+    drop(file);
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+    db.put("next", "record").unwrap();
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(db.get("next").unwrap(), Some(b"record".to_vec()));
+}
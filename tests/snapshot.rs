@@ -0,0 +1,151 @@
+extern crate koundb;
+extern crate tempfile;
+
+mod common;
+
+use common::datasize;
+use koundb::{Db, FILE_HEADER_SIZE};
+use std::fs;
+
+#[test]
+fn snapshot_ignores_later_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("a", "1").unwrap();
+    db.put("b", "2").unwrap();
+    db.put("c", "3").unwrap();
+
+    let snapshot = db.snapshot();
+    db.put("a", "10").unwrap();
+    db.delete("b").unwrap();
+    db.put("d", "4").unwrap();
+
+    assert_eq!(snapshot.get("a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(snapshot.get("b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(snapshot.get("d").unwrap(), None);
+    let items: Vec<(Vec<u8>, Vec<u8>)> = snapshot.iter().map(|item| item.unwrap()).collect();
+    assert_eq!(
+        items,
+        vec![
+            (b"a".to_vec(), b"1".to_vec()),
+            (b"b".to_vec(), b"2".to_vec()),
+            (b"c".to_vec(), b"3".to_vec()),
+        ]
+    );
+
+    assert_eq!(db.get("a").unwrap(), Some(b"10".to_vec()));
+    assert_eq!(db.get("b").unwrap(), None);
+    assert_eq!(db.get("d").unwrap(), Some(b"4".to_vec()));
+}
+
+#[test]
+fn snapshot_space_is_not_reused() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    for i in 0..100u32 {
+        db.put(format!("key{}", i), format!("old{:04}", i)).unwrap();
+    }
+
+    let snapshot = db.snapshot();
+    // 同样大小的新记录会优先复用刚释放的空间
+    for i in 0..100u32 {
+        db.delete(format!("key{}", i)).unwrap();
+        db.put(format!("new{}", i), format!("new{:04}", i)).unwrap();
+    }
+    for i in 0..100u32 {
+        assert_eq!(
+            snapshot.get(format!("key{}", i)).unwrap(),
+            Some(format!("old{:04}", i).into_bytes())
+        );
+    }
+    assert_eq!(snapshot.range("key10".."key12").count(), 2);
+    assert_eq!(db.prefix("key").count(), 0);
+    assert_eq!(db.prefix("new").count(), 100);
+}
+
+#[test]
+fn space_is_reused_after_snapshot_drop() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    for i in 0..100u32 {
+        db.put(format!("key{}", i), vec![1u8; 100]).unwrap();
+    }
+
+    let first = db.snapshot();
+    let second = first.clone();
+    for i in 0..100u32 {
+        db.put(format!("key{}", i), vec![2u8; 100]).unwrap();
+    }
+    let grown = datasize(dir.path());
+    drop(first);
+    for i in 0..100u32 {
+        db.put(format!("key{}", i), vec![3u8; 100]).unwrap();
+    }
+    // 克隆的快照仍然存活
+    assert!(datasize(dir.path()) > grown);
+    assert_eq!(second.get("key7").unwrap(), Some(vec![1u8; 100]));
+
+    drop(second);
+    let grown = datasize(dir.path());
+    for i in 0..100u32 {
+        db.put(format!("key{}", i), vec![4u8; 100]).unwrap();
+    }
+    // 删除记录会清零原记录对齐后的全部空间,文件末尾最多补齐不足16字节
+    assert!(datasize(dir.path()) < grown + 16);
+    assert_eq!(db.get("key7").unwrap(), Some(vec![4u8; 100]));
+}
+
+#[test]
+fn snapshots_taken_at_different_times() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", "1").unwrap();
+    let first = db.snapshot();
+    db.put("key", "2").unwrap();
+    let second = db.snapshot();
+    db.put("key", "3").unwrap();
+    drop(second);
+    db.put("key", "4").unwrap();
+
+    assert_eq!(first.get("key").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(db.get("key").unwrap(), Some(b"4".to_vec()));
+    db.close().unwrap();
+    assert_eq!(first.get("key").unwrap(), Some(b"1".to_vec()));
+    drop(first);
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"4".to_vec()));
+}
+
+#[test]
+fn failed_overwrite_returns_the_new_space() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("a", vec![1u8; 100]).unwrap();
+    db.put("b", vec![1u8; 100]).unwrap();
+
+    // 快照引用a时覆盖需要读取原记录,截断.data文件使读取失败
+    let snapshot = db.snapshot();
+    let path = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "data"))
+        .unwrap();
+    let bytes = fs::read(&path).unwrap();
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(32)
+        .unwrap();
+    assert!(db.put("a", vec![2u8; 100]).is_err());
+    fs::write(&path, &bytes).unwrap();
+    drop(snapshot);
+
+    // 失败的写入归还了新记录的空间,同样大小的记录写在a与b之后
+    // 每条记录129字节,按16字节对齐分配144字节
+    db.put("a", vec![3u8; 100]).unwrap();
+    assert_eq!(datasize(dir.path()), FILE_HEADER_SIZE + 2 * 144 + 129);
+    assert_eq!(db.get("a").unwrap(), Some(vec![3u8; 100]));
+    assert_eq!(db.get("b").unwrap(), Some(vec![1u8; 100]));
+}
@@ -0,0 +1,128 @@
+extern crate koundb;
+extern crate tempfile;
+
+mod common;
+
+use common::datafile;
+use koundb::{Db, FILE_HEADER_SIZE, RECORD_HEADER_SIZE};
+use std::fs;
+use std::path::Path;
+
+const PUT: u8 = 1;
+const DELETE: u8 = 2;
+
+#[test]
+fn delete_writes_tombstone_in_place() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", "value").unwrap();
+    let bytes = fs::read(datafile(dir.path())).unwrap();
+    assert_eq!(bytes[FILE_HEADER_SIZE as usize + 6], PUT);
+
+    db.delete("key").unwrap();
+    let bytes = fs::read(datafile(dir.path())).unwrap();
+    let record = &bytes[FILE_HEADER_SIZE as usize..];
+    assert_eq!(record[6], DELETE);
+    assert_eq!(&record[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + 3], b"key");
+}
+
+#[test]
+fn all_zero_key_survives_recovery() {
+    let dir = tempfile::tempdir().unwrap();
+    let zerokey = vec![0u8; 8];
+    {
+        let db = Db::open(dir.path()).unwrap();
+        db.put(zerokey.clone(), vec![0u8; 8]).unwrap();
+        db.put("other", "value").unwrap();
+    }
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get(&zerokey).unwrap(), Some(vec![0u8; 8]));
+    assert!(db.delete(&zerokey).unwrap());
+    drop(db);
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get(&zerokey).unwrap(), None);
+    assert_eq!(db.get("other").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn delete_then_put_survives_recovery() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = Db::open(dir.path()).unwrap();
+        db.put("key", "first").unwrap();
+        db.delete("key").unwrap();
+        db.put("key", "second").unwrap();
+        db.put("gone", "value").unwrap();
+        db.delete("gone").unwrap();
+    }
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"second".to_vec()));
+    assert_eq!(db.get("gone").unwrap(), None);
+    // 删除记录的空间在恢复后可以复用
+    for i in 0..20u32 {
+        db.put(format!("new{}", i), "v").unwrap();
+    }
+    assert_eq!(db.get("key").unwrap(), Some(b"second".to_vec()));
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"second".to_vec()));
+    assert_eq!(db.get("gone").unwrap(), None);
+    assert_eq!(db.get("new19").unwrap(), Some(b"v".to_vec()));
+}
+
+// 目录中所有文件是否都不包含needle
+fn absent_from_files(dir: &Path, needle: &[u8]) -> bool {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| fs::read(entry.unwrap().path()).unwrap())
+        .all(|bytes| !bytes.windows(needle.len()).any(|window| window == needle))
+}
+
+#[test]
+fn delete_erases_the_old_value() {
+    let dir = tempfile::tempdir().unwrap();
+    let secret = b"SUPER-SECRET-PAYLOAD";
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", &secret[..]).unwrap();
+    db.put("other", "value").unwrap();
+    assert!(!absent_from_files(dir.path(), secret));
+
+    // wal在同步前仍保存写入的记录,.data文件中的value立即被清除
+    db.delete("key").unwrap();
+    let bytes = fs::read(datafile(dir.path())).unwrap();
+    assert!(!bytes.windows(secret.len()).any(|window| window == secret));
+    db.close().unwrap();
+    assert!(absent_from_files(dir.path(), secret));
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), None);
+    assert_eq!(db.get("other").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn overwrite_erases_the_old_value_after_snapshot_drop() {
+    let dir = tempfile::tempdir().unwrap();
+    let secret = b"SUPER-SECRET-PAYLOAD";
+    // secret位于记录末尾,之后复用这段空间的小记录不会覆盖它
+    let value = [vec![0u8; 64], secret.to_vec()].concat();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", value.clone()).unwrap();
+
+    // 快照仍可以读取被覆盖的value
+    let snapshot = db.snapshot();
+    db.put("key", "public").unwrap();
+    assert_eq!(snapshot.get("key").unwrap(), Some(value));
+    drop(snapshot);
+
+    // 快照释放后的下一次写入清除原value
+    db.put("other", "value").unwrap();
+    db.close().unwrap();
+    assert!(absent_from_files(dir.path(), secret));
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"public".to_vec()));
+}
@@ -0,0 +1,136 @@
+extern crate koundb;
+extern crate tempfile;
+
+use koundb::{Db, Error};
+
+fn balance(value: Option<Vec<u8>>) -> i64 {
+    String::from_utf8(value.unwrap()).unwrap().parse().unwrap()
+}
+
+#[test]
+fn transaction_commits_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("alice", "100").unwrap();
+    db.put("bob", "50").unwrap();
+
+    let moved = db
+        .transaction(|txn| {
+            let alice = balance(txn.get("alice")?);
+            let bob = balance(txn.get("bob")?);
+            txn.put("alice", (alice - 30).to_string())?;
+            txn.put("bob", (bob + 30).to_string())?;
+            // 事务能读到自己的写入
+            assert_eq!(txn.get("alice")?, Some(b"70".to_vec()));
+            Ok(30)
+        })
+        .unwrap();
+    assert_eq!(moved, 30);
+    assert_eq!(db.get("alice").unwrap(), Some(b"70".to_vec()));
+    assert_eq!(db.get("bob").unwrap(), Some(b"80".to_vec()));
+}
+
+#[test]
+fn closure_error_discards_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", "value").unwrap();
+
+    let result: Result<(), Error> = db.transaction(|txn| {
+        txn.delete("key")?;
+        txn.put("other", "value")?;
+        Err(Error::InvalidKey("abort".to_string()))
+    });
+    assert!(result.is_err());
+    assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(db.get("other").unwrap(), None);
+}
+
+#[test]
+fn failed_commit_discards_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("alice", "100").unwrap();
+    db.put("bob", "50").unwrap();
+
+    // 写入在提交时分配空间失败,先分配的修改同样不生效
+    let result = db.transaction(|txn| {
+        txn.put("alice", "70")?;
+        txn.delete("bob")?;
+        txn.put("carol", vec![0u8; 33 << 20])?;
+        Ok(())
+    });
+    match result {
+        Err(Error::Allocatefail(_)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(db.get("alice").unwrap(), Some(b"100".to_vec()));
+    assert_eq!(db.get("bob").unwrap(), Some(b"50".to_vec()));
+    assert_eq!(db.get("carol").unwrap(), None);
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("alice").unwrap(), Some(b"100".to_vec()));
+    assert_eq!(db.get("bob").unwrap(), Some(b"50".to_vec()));
+    assert_eq!(db.get("carol").unwrap(), None);
+}
+
+#[test]
+fn rewritten_read_conflicts() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("alice", "100").unwrap();
+    db.put("bob", "50").unwrap();
+
+    let mut txn = db.begin();
+    let alice = balance(txn.get("alice").unwrap());
+    txn.put("alice", (alice - 30).to_string()).unwrap();
+    txn.put("bob", "80").unwrap();
+    // 事务开始后alice被改写为同样的值
+    db.put("alice", "100").unwrap();
+
+    match db.commit(txn) {
+        Err(Error::Conflict(ref key)) => assert_eq!(key, b"alice"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(db.get("alice").unwrap(), Some(b"100".to_vec()));
+    assert_eq!(db.get("bob").unwrap(), Some(b"50".to_vec()));
+}
+
+#[test]
+fn read_of_missing_key_conflicts_with_insert() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+
+    let mut txn = db.begin();
+    assert_eq!(txn.get("key").unwrap(), None);
+    txn.put("key", "from txn").unwrap();
+    db.put("key", "outside").unwrap();
+    match db.commit(txn) {
+        Err(Error::Conflict(..)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(db.get("key").unwrap(), Some(b"outside".to_vec()));
+}
+
+#[test]
+fn unrelated_writes_do_not_conflict() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("a", "1").unwrap();
+    db.put("b", "2").unwrap();
+
+    let mut txn = db.begin();
+    assert_eq!(txn.get("a").unwrap(), Some(b"1".to_vec()));
+    txn.put("b", "20").unwrap();
+    // 未读过的key被改写不会冲突,事务的写入覆盖它
+    db.put("b", "3").unwrap();
+    db.put("c", "3").unwrap();
+    // 读到的仍是事务开始时的快照
+    assert_eq!(txn.get("c").unwrap(), None);
+    db.delete("c").unwrap();
+    db.commit(txn).unwrap();
+
+    assert_eq!(db.get("a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(db.get("b").unwrap(), Some(b"20".to_vec()));
+}
@@ -0,0 +1,124 @@
+extern crate koundb;
+extern crate tempfile;
+
+mod common;
+
+use common::datasize;
+use koundb::Db;
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+const SHORT: Duration = Duration::from_millis(50);
+const LONG: Duration = Duration::from_secs(3600);
+
+fn wait_expiry() {
+    thread::sleep(Duration::from_millis(120));
+}
+
+#[test]
+fn expired_key_is_invisible() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put_with_ttl("session", "token", SHORT).unwrap();
+    db.put_with_ttl("long", "value", LONG).unwrap();
+    db.put("plain", "value").unwrap();
+    let snapshot = db.snapshot();
+    assert_eq!(db.get("session").unwrap(), Some(b"token".to_vec()));
+
+    wait_expiry();
+    assert_eq!(db.get("session").unwrap(), None);
+    assert_eq!(snapshot.get("session").unwrap(), None);
+    assert_eq!(db.get("long").unwrap(), Some(b"value".to_vec()));
+    let keys: Vec<Vec<u8>> = db.iter().map(|item| item.unwrap().0).collect();
+    assert_eq!(keys, vec![b"long".to_vec(), b"plain".to_vec()]);
+    assert_eq!(db.iter().rev().count(), 2);
+    assert_eq!(snapshot.iter().count(), 2);
+    // 删除已过期的key等同于删除不存在的key
+    assert!(!db.delete("session").unwrap());
+}
+
+#[test]
+fn huge_ttl_never_expires() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put_with_ttl("max", "value", Duration::MAX).unwrap();
+    db.put_with_ttl("large", "value", Duration::from_secs(u64::MAX / 1000))
+        .unwrap();
+    assert_eq!(db.get("max").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(db.get("large").unwrap(), Some(b"value".to_vec()));
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("max").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(db.get("large").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn put_replaces_expiry() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put_with_ttl("a", "1", SHORT).unwrap();
+    db.put("a", "2").unwrap();
+    db.put("b", "1").unwrap();
+    db.put_with_ttl("b", "2", SHORT).unwrap();
+
+    wait_expiry();
+    assert_eq!(db.get("a").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(db.get("b").unwrap(), None);
+    db.put("b", "3").unwrap();
+    assert_eq!(db.get("b").unwrap(), Some(b"3".to_vec()));
+}
+
+#[test]
+fn expiry_survives_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put_with_ttl("short", "value", SHORT).unwrap();
+    db.put_with_ttl("long", "value", LONG).unwrap();
+    db.close().unwrap();
+
+    // 从.index文件恢复
+    wait_expiry();
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("short").unwrap(), None);
+    assert_eq!(db.get("long").unwrap(), Some(b"value".to_vec()));
+    db.close().unwrap();
+
+    // 扫描.data文件恢复
+    for entry in fs::read_dir(dir.path()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "index") {
+            fs::remove_file(path).unwrap();
+        }
+    }
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("short").unwrap(), None);
+    assert_eq!(db.get("long").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn expired_space_is_reused_after_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    for i in 0..50u32 {
+        db.put_with_ttl(format!("key{}", i), vec![1u8; 100], SHORT)
+            .unwrap();
+    }
+    db.close().unwrap();
+    wait_expiry();
+
+    let size = datasize(dir.path());
+    let db = Db::open(dir.path()).unwrap();
+    for i in 0..50u32 {
+        db.put(format!("new{}", i), vec![2u8; 100]).unwrap();
+    }
+    db.close().unwrap();
+    assert_eq!(datasize(dir.path()), size);
+
+    let db = Db::open(dir.path()).unwrap();
+    for i in 0..50u32 {
+        assert_eq!(db.get(format!("key{}", i)).unwrap(), None);
+        assert_eq!(db.get(format!("new{}", i)).unwrap(), Some(vec![2u8; 100]));
+    }
+}
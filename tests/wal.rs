@@ -0,0 +1,144 @@
+extern crate koundb;
+extern crate tempfile;
+
+mod common;
+
+use common::datafile;
+use koundb::{Db, FILE_HEADER_SIZE};
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+fn walsize(dir: &Path) -> u64 {
+    fs::metadata(dir.join("koundb.wal")).unwrap().len()
+}
+
+#[test]
+fn close_truncates_wal() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("key", "value").unwrap();
+    assert!(walsize(dir.path()) > FILE_HEADER_SIZE);
+    db.close().unwrap();
+    assert_eq!(walsize(dir.path()), FILE_HEADER_SIZE);
+}
+
+#[test]
+fn replay_repairs_torn_write() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = Db::open(dir.path()).unwrap();
+        db.put("key", vec![7u8; 200]).unwrap();
+        db.put("other", "value").unwrap();
+    }
+
+    // 记录已写入wal,但写.data文件时崩溃
+    let path = datafile(dir.path());
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(FILE_HEADER_SIZE + 100)).unwrap();
+    file.write_all(&[0u8; 64]).unwrap();
+    drop(file);
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(vec![7u8; 200]));
+    assert_eq!(db.get("other").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(walsize(dir.path()), FILE_HEADER_SIZE);
+}
+
+#[test]
+fn replay_restores_lost_data_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    db.put("old", "value").unwrap();
+    db.close().unwrap();
+    let len = fs::metadata(datafile(dir.path())).unwrap().len();
+
+    {
+        let db = Db::open(dir.path()).unwrap();
+        db.put("new", "value").unwrap();
+        db.delete("old").unwrap();
+    }
+    // .data文件的修改没有落盘
+    let file = OpenOptions::new()
+        .write(true)
+        .open(datafile(dir.path()))
+        .unwrap();
+    file.set_len(len).unwrap();
+    drop(file);
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("new").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(db.get("old").unwrap(), None);
+}
+
+#[test]
+fn torn_wal_tail_is_discarded() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = Db::open(dir.path()).unwrap();
+        db.put("key", "value").unwrap();
+    }
+    // 写wal时崩溃,最后一帧不完整
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(dir.path().join("koundb.wal"))
+        .unwrap();
+    file.write_all(&[200, 0, 0, 0, 1, 2, 3, 4, 5]).unwrap();
+    drop(file);
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"value".to_vec()));
+    db.put("key", "changed").unwrap();
+    db.close().unwrap();
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"changed".to_vec()));
+}
+
+#[test]
+fn wal_is_checkpointed_when_full() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path()).unwrap();
+    for i in 0..100u32 {
+        db.put(format!("key{}", i), vec![i as u8; 64 * 1024])
+            .unwrap();
+    }
+    assert!(walsize(dir.path()) <= (4 << 20) + 64 * 1024 + 1024);
+    drop(db);
+
+    let db = Db::open(dir.path()).unwrap();
+    for i in 0..100u32 {
+        assert_eq!(
+            db.get(format!("key{}", i)).unwrap(),
+            Some(vec![i as u8; 64 * 1024])
+        );
+    }
+}
+
+#[test]
+fn overwrite_is_one_wal_batch() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = Db::open(dir.path()).unwrap();
+        db.put("key", "old").unwrap();
+        db.close().unwrap();
+    }
+    let path = datafile(dir.path());
+    let bytes = fs::read(&path).unwrap();
+    {
+        let db = Db::open(dir.path()).unwrap();
+        db.put("key", "new").unwrap();
+    }
+    // 覆盖写入时崩溃: .data文件的修改没有落盘,wal的最后一帧不完整
+    fs::write(&path, &bytes).unwrap();
+    let wal = OpenOptions::new()
+        .write(true)
+        .open(dir.path().join("koundb.wal"))
+        .unwrap();
+    let len = wal.metadata().unwrap().len();
+    wal.set_len(len - 1).unwrap();
+    drop(wal);
+
+    let db = Db::open(dir.path()).unwrap();
+    assert_eq!(db.get("key").unwrap(), Some(b"old".to_vec()));
+}